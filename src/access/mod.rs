@@ -0,0 +1,14 @@
+use anyhow::Result;
+use model::ACCESS_LOG;
+use tracing::info;
+
+use crate::common::config::CONFIG;
+
+pub mod model;
+
+pub fn init() -> Result<()> {
+    if ACCESS_LOG.load()? {
+        info!("访问日志写入: {:?}", CONFIG.access_log.file);
+    }
+    Ok(())
+}
@@ -0,0 +1,143 @@
+use std::{
+    io::{BufWriter, Write},
+    sync::{Arc, Mutex},
+    thread::{self, JoinHandle},
+};
+
+use anyhow::Result;
+use chrono::{SecondsFormat, Utc};
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use tokio::sync::mpsc;
+use tracing::error;
+
+use crate::{
+    common::config::CONFIG,
+    protocol::model::Context,
+    util::{RotatingFile, parse_size},
+};
+
+// 一条隧道的访问记录
+#[derive(Debug, Serialize)]
+pub struct Record {
+    pub time: String,
+    pub listener: String,
+    pub client: String,
+    pub user: Option<String>,
+    // 入站协议: http / socks5 / transparent
+    pub protocol: &'static str,
+    pub target: Option<String>,
+    // 出站方式: 上游地址、direct 或 block
+    pub upstream: Option<String>,
+    // 选择上游的次数
+    pub attempts: usize,
+    pub upload: u64,
+    pub download: u64,
+    pub duration_ms: u64,
+    pub reason: String,
+}
+
+impl Record {
+    pub fn new(ctx: &Context) -> Self {
+        let (upload, download) = ctx.activity.bytes();
+        Record {
+            time: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+            listener: ctx.listener.name.clone(),
+            client: ctx.client_address.to_string(),
+            user: ctx.user.clone(),
+            protocol: ctx.inbound,
+            target: ctx.target.as_ref().map(|target| target.to_string()),
+            upstream: ctx.upstream.clone(),
+            attempts: ctx.attempts,
+            upload,
+            download,
+            duration_ms: ctx.activity.elapsed().as_millis() as u64,
+            reason: ctx.reason.clone().unwrap_or_default(),
+        }
+    }
+}
+
+// 写入队列的长度，写入跟不上时丢弃新的记录，避免阻塞连接
+const QUEUE_SIZE: usize = 4096;
+
+// 访问记录通过队列交给独立的写入线程，连接结束时不在 tokio 工作线程上做文件 I/O
+pub struct AccessLogger {
+    sender: Mutex<Option<mpsc::Sender<Record>>>,
+    writer: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl Default for AccessLogger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AccessLogger {
+    pub fn new() -> Self {
+        AccessLogger {
+            sender: Mutex::new(None),
+            writer: Mutex::new(None),
+        }
+    }
+
+    // 打开配置的日志文件并启动写入线程，未配置时返回 false
+    pub fn load(&self) -> Result<bool> {
+        let config = &CONFIG.access_log;
+        let Some(path) = &config.file else {
+            return Ok(false);
+        };
+        let max_size = config.max_size.as_deref().map(parse_size).transpose()?;
+        let file = RotatingFile::open(path, max_size, config.rotation, config.keep)?;
+        let (sender, receiver) = mpsc::channel(QUEUE_SIZE);
+        let writer = thread::Builder::new()
+            .name("access-log".to_string())
+            .spawn(move || run(receiver, BufWriter::new(file)))?;
+        *self.sender.lock().unwrap() = Some(sender);
+        *self.writer.lock().unwrap() = Some(writer);
+        Ok(true)
+    }
+
+    pub fn write(&self, record: Record) {
+        let sender = self.sender.lock().unwrap();
+        let Some(sender) = sender.as_ref() else {
+            return;
+        };
+        if let Err(e) = sender.try_send(record) {
+            error!("写入访问日志失败: {}", e);
+        }
+    }
+
+    // 关闭队列，等待写入线程写完剩余的记录
+    pub async fn close(&self) {
+        self.sender.lock().unwrap().take();
+        let Some(writer) = self.writer.lock().unwrap().take() else {
+            return;
+        };
+        let _ = tokio::task::spawn_blocking(move || writer.join()).await;
+    }
+}
+
+// 写入线程: 逐条写入缓冲区，队列暂时为空时写入文件
+fn run(mut receiver: mpsc::Receiver<Record>, mut writer: BufWriter<RotatingFile>) {
+    while let Some(record) = receiver.blocking_recv() {
+        let mut result = serde_json::to_string(&record)
+            .map_err(anyhow::Error::from)
+            .and_then(|line| Ok(writeln!(writer, "{}", line)?));
+        if receiver.is_empty() {
+            result = result.and_then(|_| Ok(writer.flush()?));
+        }
+        if let Err(e) = result {
+            error!("写入访问日志失败: {}", e);
+        }
+    }
+    if let Err(e) = writer.flush() {
+        error!("写入访问日志失败: {}", e);
+    }
+}
+
+pub fn init() -> Result<Arc<AccessLogger>> {
+    Ok(Arc::new(AccessLogger::new()))
+}
+
+// 全局访问日志
+pub static ACCESS_LOG: Lazy<Arc<AccessLogger>> = Lazy::new(|| init().unwrap());
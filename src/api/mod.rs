@@ -0,0 +1,34 @@
+use anyhow::Result;
+use hyper::{server::conn::http1, service::service_fn};
+use hyper_util::rt::TokioIo;
+use tokio::net::TcpListener;
+use tracing::{error, info};
+
+use crate::common::config::CONFIG;
+
+mod service;
+
+// 启动 API 服务
+pub async fn run() -> Result<()> {
+    let address = format!("{}:{}", CONFIG.api.host, CONFIG.api.port);
+    let listener = TcpListener::bind(&address).await?;
+    info!("API 服务启动在: {}", address);
+
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                tokio::spawn(async move {
+                    if let Err(e) = http1::Builder::new()
+                        .serve_connection(TokioIo::new(stream), service_fn(service::handle))
+                        .await
+                    {
+                        error!("API 连接处理出错: {}", e);
+                    }
+                });
+            }
+            Err(e) => {
+                error!("API 接受连接失败: {}", e);
+            }
+        }
+    }
+}
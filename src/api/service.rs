@@ -0,0 +1,367 @@
+use std::convert::Infallible;
+
+use http_body_util::{BodyExt, Full, Limited};
+use hyper::{
+    Method, Request, Response, StatusCode,
+    body::{Bytes, Incoming},
+    header::AUTHORIZATION,
+};
+use percent_encoding::percent_decode_str;
+use serde::{Deserialize, de::DeserializeOwned};
+use serde_json::{Value, json};
+use subtle::ConstantTimeEq;
+use tracing::{error, info};
+
+use crate::{
+    common::config::CONFIG,
+    metrics::model::METRICS,
+    protocol::model::Protocol,
+    proxy::{
+        anonymity::Anonymity,
+        model::{PROXY_POOL, Proxy},
+        selector::Selector,
+    },
+    server::port_map::PORT_MAPS,
+    traffic::model::TRAFFIC,
+};
+
+// 请求体大小上限
+const MAX_BODY_SIZE: usize = 1024 * 1024;
+
+// 获取上游时单次最多返回的数量
+const MAX_COUNT: usize = 100;
+
+pub async fn handle(request: Request<Incoming>) -> Result<Response<Full<Bytes>>, Infallible> {
+    let method = request.method().clone();
+    let path = request.uri().path().to_string();
+
+    // 所有接口都需要认证，未配置令牌时拒绝全部请求
+    // 客户端令牌只能获取上游和报告故障，其余接口需要管理令牌
+    let matches = |token: &Option<String>| {
+        token.as_deref().is_some_and(|token| {
+            bearer(&request)
+                .is_some_and(|bearer| bool::from(bearer.as_bytes().ct_eq(token.as_bytes())))
+        })
+    };
+    let admin = matches(&CONFIG.api.token);
+    let authorized = match (&method, path.as_str()) {
+        (&Method::GET, "/get") | (&Method::POST, "/report") => {
+            admin || matches(&CONFIG.api.client_token)
+        }
+        _ => admin,
+    };
+    if !authorized {
+        let message = match CONFIG.api.token {
+            Some(_) => "unauthorized",
+            None => "api.token not configured",
+        };
+        return Ok(json(StatusCode::UNAUTHORIZED, json!({ "error": message })));
+    }
+
+    let response = match (&method, path.as_str()) {
+        (&Method::GET, "/port_map") => port_map().await,
+        (&Method::GET, "/proxies") => proxies().await,
+        (&Method::POST, "/proxies") => add(request).await,
+        (&Method::DELETE, path) if path.starts_with("/proxies/") => {
+            // 地址中的 : 和 / 可能经过 URL 编码，例如 socks5%3A%2F%2F1.2.3.4%3A1080
+            match percent_decode_str(&path["/proxies/".len()..]).decode_utf8() {
+                Ok(address) => remove(&address).await,
+                Err(_) => bad_request("invalid proxy address"),
+            }
+        }
+        (&Method::POST, "/test") => test(),
+        (&Method::POST, "/rotate") => rotate().await,
+        (&Method::GET, "/config") => config(),
+        (&Method::GET, "/metrics") => metrics().await,
+        (&Method::GET, "/get") => get(request.uri().query().unwrap_or_default()).await,
+        (&Method::POST, "/report") => report(request).await,
+        _ => json(StatusCode::NOT_FOUND, json!({ "error": "not found" })),
+    };
+    Ok(response)
+}
+
+fn json(status: StatusCode, value: Value) -> Response<Full<Bytes>> {
+    let mut response = Response::new(Full::new(Bytes::from(value.to_string())));
+    *response.status_mut() = status;
+    response.headers_mut().insert(
+        hyper::header::CONTENT_TYPE,
+        hyper::header::HeaderValue::from_static("application/json"),
+    );
+    response
+}
+
+fn bad_request(message: impl ToString) -> Response<Full<Bytes>> {
+    json(
+        StatusCode::BAD_REQUEST,
+        json!({ "error": message.to_string() }),
+    )
+}
+
+// 读取 JSON 格式的请求体
+async fn read_json<T: DeserializeOwned>(request: Request<Incoming>) -> Result<T, String> {
+    let body = Limited::new(request.into_body(), MAX_BODY_SIZE)
+        .collect()
+        .await
+        .map_err(|e| e.to_string())?
+        .to_bytes();
+    serde_json::from_slice(&body).map_err(|e| e.to_string())
+}
+
+// 读取 Authorization: Bearer <token> 中的令牌
+fn bearer(request: &Request<Incoming>) -> Option<&str> {
+    request
+        .headers()
+        .get(AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+        .map(str::trim)
+}
+
+// 当前端口到上游的映射表
+async fn port_map() -> Response<Full<Bytes>> {
+    let mut listeners = Vec::new();
+    for port_map in PORT_MAPS.read().await.iter() {
+        let table = port_map.table.read().await;
+        let ports: Vec<Value> = table
+            .iter()
+            .map(|(port, proxy)| {
+                json!({ "port": port, "upstream": proxy.show(), "tags": proxy.labels() })
+            })
+            .collect();
+        listeners.push(json!({
+            "name": port_map.listener.name,
+            "group": port_map.listener.group,
+            "selector": port_map.listener.selector,
+            "ports": ports,
+        }));
+    }
+    json(StatusCode::OK, json!({ "listeners": listeners }))
+}
+
+// 代理池中的全部上游，以及各自的连接数和当前周期的流量
+async fn proxies() -> Response<Full<Bytes>> {
+    let proxies = PROXY_POOL.all(&Selector::new()).await;
+    let connections = PROXY_POOL.connections.lock().unwrap().clone();
+    let traffic = TRAFFIC.snapshot();
+
+    let proxies: Vec<Value> = proxies
+        .iter()
+        .map(|proxy| {
+            let key = format!("proxy:{}", proxy.show());
+            let active = connections.get(&key).copied().unwrap_or_default();
+            let status = match proxy.max_connections {
+                Some(max) if active >= max => "saturated",
+                _ => "available",
+            };
+            let counter = traffic.counters.get(&key).copied().unwrap_or_default();
+            json!({
+                "address": proxy.show(),
+                "group": proxy.group,
+                "tags": proxy.labels(),
+                "max_connections": proxy.max_connections,
+                "connections": active,
+                "status": status,
+                "upload": counter.upload,
+                "download": counter.download,
+            })
+        })
+        .collect();
+    json(
+        StatusCode::OK,
+        json!({ "period": traffic.period, "total": proxies.len(), "proxies": proxies }),
+    )
+}
+
+#[derive(Deserialize)]
+struct AddRequest {
+    // 与代理文件相同格式的行，例如 "socks5://1.2.3.4:1080 res country=DE"
+    proxies: Vec<String>,
+}
+
+// 添加上游
+async fn add(request: Request<Incoming>) -> Response<Full<Bytes>> {
+    let request: AddRequest = match read_json(request).await {
+        Ok(request) => request,
+        Err(e) => return bad_request(e),
+    };
+
+    let mut proxies = Vec::new();
+    for line in &request.proxies {
+        let parsed = Proxy::from_line(line);
+        if parsed.is_empty() {
+            return bad_request(format!("invalid proxy: {}", line));
+        }
+        proxies.extend(parsed);
+    }
+    match PROXY_POOL.add(proxies).await {
+        Ok(added) => json(StatusCode::OK, json!({ "added": added })),
+        Err(e) => {
+            error!("添加上游失败: {}", e);
+            json(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({ "error": e.to_string() }),
+            )
+        }
+    }
+}
+
+// 移除上游，address 为 scheme://host:port
+async fn remove(address: &str) -> Response<Full<Bytes>> {
+    match PROXY_POOL.remove(address).await {
+        Ok(true) => json(StatusCode::OK, json!({ "removed": address })),
+        Ok(false) => json(
+            StatusCode::NOT_FOUND,
+            json!({ "error": format!("proxy not found: {}", address) }),
+        ),
+        Err(e) => {
+            error!("移除上游失败: {}", e);
+            json(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({ "error": e.to_string() }),
+            )
+        }
+    }
+}
+
+// 在后台重新检测所有上游，检测失败的上游会被移出代理池
+fn test() -> Response<Full<Bytes>> {
+    tokio::spawn(async {
+        if let Err(e) = PROXY_POOL.test().await {
+            error!("重新检测上游失败: {}", e);
+        }
+    });
+    json(StatusCode::ACCEPTED, json!({ "status": "testing" }))
+}
+
+// 强制轮换上游
+async fn rotate() -> Response<Full<Bytes>> {
+    PROXY_POOL.rotate().await;
+    json(StatusCode::OK, json!({ "status": "rotated" }))
+}
+
+// 当前生效的配置，隐藏密码和令牌
+fn config() -> Response<Full<Bytes>> {
+    let mut config = match serde_json::to_value(&*CONFIG) {
+        Ok(config) => config,
+        Err(e) => {
+            return json(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({ "error": e.to_string() }),
+            );
+        }
+    };
+    if let Some(users) = config["user"].as_array_mut() {
+        for user in users {
+            user["password"] = json!("******");
+        }
+    }
+    for key in ["token", "client_token"] {
+        if !config["api"][key].is_null() {
+            config["api"][key] = json!("******");
+        }
+    }
+    json(StatusCode::OK, config)
+}
+
+// 为直接使用上游的客户端获取上游
+// 参数: protocol=http|socks5, count=N, max_latency=毫秒, anonymity=最低匿名级别，其余参数作为标签过滤
+async fn get(query: &str) -> Response<Full<Bytes>> {
+    let mut scheme = None;
+    let mut count = 1;
+    let mut selector = Selector::new();
+    for (key, value) in query.split('&').filter_map(|pair| pair.split_once('=')) {
+        match key {
+            "protocol" => {
+                scheme = match value {
+                    "http" => Some(Protocol::Http),
+                    "socks5" => Some(Protocol::Socks5),
+                    _ => return bad_request(format!("invalid protocol: {}", value)),
+                }
+            }
+            "count" => match value.parse() {
+                Ok(0) | Err(_) => return bad_request(format!("invalid count: {}", value)),
+                Ok(value) => count = value.min(MAX_COUNT),
+            },
+            "max_latency" => match value.parse() {
+                Ok(value) => selector.max_latency = Some(value),
+                Err(_) => return bad_request(format!("invalid max_latency: {}", value)),
+            },
+            "anonymity" => match Anonymity::from(value) {
+                Ok(value) => selector.min_anonymity = Some(value),
+                Err(_) => return bad_request(format!("invalid anonymity: {}", value)),
+            },
+            _ => selector = selector.with(key, value),
+        }
+    }
+
+    let proxies = PROXY_POOL.pick(scheme, &selector, count).await;
+    if proxies.is_empty() {
+        return json(
+            StatusCode::NOT_FOUND,
+            json!({ "error": format!("no proxy matches: {}", selector) }),
+        );
+    }
+    let proxies: Vec<Value> = proxies
+        .iter()
+        .map(|proxy| {
+            json!({
+                "address": proxy.show(),
+                "host": proxy.host,
+                "port": proxy.port,
+                "tags": proxy.labels(),
+            })
+        })
+        .collect();
+    json(StatusCode::OK, json!({ "proxies": proxies }))
+}
+
+#[derive(Deserialize)]
+struct ReportRequest {
+    // 上游地址，格式为 scheme://host:port
+    proxy: String,
+    reason: Option<String>,
+}
+
+// 客户端报告无法使用的上游
+async fn report(request: Request<Incoming>) -> Response<Full<Bytes>> {
+    let request: ReportRequest = match read_json(request).await {
+        Ok(request) => request,
+        Err(e) => return bad_request(e),
+    };
+    if let Some(reason) = &request.reason {
+        info!("上游故障原因: {} - {}", request.proxy, reason);
+    }
+    match PROXY_POOL.report(&request.proxy).await {
+        Ok(true) => json(StatusCode::OK, json!({ "reported": request.proxy })),
+        Ok(false) => json(
+            StatusCode::NOT_FOUND,
+            json!({ "error": format!("proxy not found: {}", request.proxy) }),
+        ),
+        Err(e) => {
+            error!("处理上游故障报告失败: {}", e);
+            json(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({ "error": e.to_string() }),
+            )
+        }
+    }
+}
+
+// Prometheus 指标
+async fn metrics() -> Response<Full<Bytes>> {
+    match METRICS.gather().await {
+        Ok(text) => {
+            let mut response = Response::new(Full::new(Bytes::from(text)));
+            response.headers_mut().insert(
+                hyper::header::CONTENT_TYPE,
+                hyper::header::HeaderValue::from_static("text/plain; version=0.0.4"),
+            );
+            response
+        }
+        Err(e) => json(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            json!({ "error": e.to_string() }),
+        ),
+    }
+}
@@ -0,0 +1,113 @@
+use std::{collections::HashSet, fs, path::Path};
+
+use anyhow::Result;
+
+use crate::{
+    common::config::{self, CONFIG},
+    geoip,
+    proxy::{
+        format::{self, ListFormat},
+        model::{self, Proxy},
+    },
+};
+
+// 检测代理列表并输出每个代理的结果
+pub async fn check(file: &str, format: ListFormat) -> Result<()> {
+    let proxies = format::read(file, format)?;
+    if proxies.is_empty() {
+        return Err(anyhow::anyhow!("代理列表为空: {}", file));
+    }
+    geoip::init().await?;
+
+    println!(
+        "开始代理检测... 共有代理: {} 并发数: {}",
+        proxies.len(),
+        CONFIG.proxy.max_test_count
+    );
+    let results = model::check(proxies, true).await;
+
+    let mut available = 0;
+    for (proxy, result) in &results {
+        match result {
+            Ok(true) => {
+                available += 1;
+                println!("可用  {}  {}", proxy.show(), report(proxy));
+            }
+            Ok(false) => println!("失败  {}  出口 IP 未隐藏", proxy.show()),
+            Err(e) => println!("失败  {}  {}", proxy.show(), e),
+        }
+    }
+    println!("共 {} 个代理，可用 {} 个", results.len(), available);
+    Ok(())
+}
+
+// 检测时记录的延迟、出口 IP 和地理位置
+fn report(proxy: &Proxy) -> String {
+    [
+        "latency",
+        "egress",
+        "egress_country",
+        "egress_city",
+        "egress_asn",
+    ]
+    .iter()
+    .filter_map(|key| proxy.label(key).map(|value| format!("{}={}", key, value)))
+    .collect::<Vec<_>>()
+    .join(" ")
+}
+
+// 将代理列表合并到配置的代理文件
+pub fn import(file: &str, format: ListFormat) -> Result<()> {
+    let path = &CONFIG.proxy.proxy_file;
+    let mut proxies = if Path::new(path).exists() {
+        format::read(path, ListFormat::Line)?
+    } else {
+        Vec::new()
+    };
+    let mut existing: HashSet<String> = proxies.iter().map(Proxy::show).collect();
+
+    let mut added = 0;
+    let mut skipped = 0;
+    for proxy in format::read(file, format)? {
+        if existing.insert(proxy.show()) {
+            proxies.push(proxy);
+            added += 1;
+        } else {
+            skipped += 1;
+        }
+    }
+    fs::write(path, format::render(&proxies, ListFormat::Line)?)?;
+    println!(
+        "导入 {} 个代理，跳过 {} 个已存在的代理，写入: {}",
+        added, skipped, path
+    );
+    Ok(())
+}
+
+// 按指定格式导出配置的代理文件
+pub fn export(output: Option<&str>, format: ListFormat) -> Result<()> {
+    let proxies = format::read(&CONFIG.proxy.proxy_file, ListFormat::Line)?;
+    let content = format::render(&proxies, format)?;
+    match output {
+        Some(output) => {
+            fs::write(output, content)?;
+            println!("导出 {} 个代理到: {}", proxies.len(), output);
+        }
+        None => println!("{}", content),
+    }
+    Ok(())
+}
+
+// 检查配置的取值范围、引用的文件和配置项之间的约束
+pub fn validate() -> Result<()> {
+    config::load(true)?;
+    println!("配置文件有效: {}", config::path());
+    Ok(())
+}
+
+// 输出最终生效的配置
+pub fn config() -> Result<()> {
+    let config = config::read()?;
+    print!("{}", toml::to_string(&config)?);
+    Ok(())
+}
@@ -0,0 +1,57 @@
+use clap::{Parser, Subcommand};
+
+use crate::proxy::format::ListFormat;
+
+pub mod command;
+
+#[derive(Debug, Parser)]
+#[command(name = "x-proxy-pool", version, about = "代理池服务")]
+pub struct Cli {
+    #[arg(
+        short,
+        long,
+        help = "配置文件路径，默认为 config.toml，不存在时使用默认配置"
+    )]
+    pub config: Option<String>,
+    #[arg(
+        short = 's',
+        long = "set",
+        value_name = "KEY=VALUE",
+        help = "覆盖配置项，例如 proxy.max_test_count=20，可重复使用"
+    )]
+    pub overrides: Vec<String>,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+// 未指定子命令时启动服务
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    #[command(about = "启动代理服务（默认）")]
+    Serve,
+    #[command(about = "检测代理列表并输出报告，不启动服务")]
+    Check {
+        #[arg(help = "代理列表文件")]
+        file: String,
+        #[arg(short, long, value_enum, default_value_t, help = "代理列表格式")]
+        format: ListFormat,
+    },
+    #[command(about = "将代理列表导入到配置的代理文件，跳过已存在的代理")]
+    Import {
+        #[arg(help = "代理列表文件")]
+        file: String,
+        #[arg(short, long, value_enum, default_value_t, help = "代理列表格式")]
+        format: ListFormat,
+    },
+    #[command(about = "按指定格式导出配置的代理文件")]
+    Export {
+        #[arg(short, long, help = "输出文件，未指定时输出到终端")]
+        output: Option<String>,
+        #[arg(short, long, value_enum, default_value_t, help = "代理列表格式")]
+        format: ListFormat,
+    },
+    #[command(about = "检查配置文件")]
+    Validate,
+    #[command(about = "输出合并默认值、配置文件、环境变量和命令行参数后的配置")]
+    Config,
+}
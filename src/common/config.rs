@@ -1,32 +1,117 @@
-use std::{fs, path::Path};
+use std::{collections::BTreeMap, fs, io::ErrorKind, sync::Mutex};
 
 use anyhow::Result;
-use once_cell::sync::Lazy;
+use ipnet::IpNet;
+use once_cell::sync::{Lazy, OnceCell};
 use serde::{Deserialize, Serialize};
+use toml::{Table, Value};
+
+use super::validate::validate;
+
+// 默认的配置文件路径
+const DEFAULT_CONFIG_PATH: &str = "config.toml";
+
+// 命令行指定的配置文件路径
+static CONFIG_PATH: OnceCell<String> = OnceCell::new();
+
+// 命令行指定的配置项，优先于环境变量
+static CONFIG_OVERRIDES: OnceCell<Vec<String>> = OnceCell::new();
+
+// 启动前已校验的配置，首次访问 CONFIG 时使用
+static LOADED: Mutex<Option<Config>> = Mutex::new(None);
+
+// 覆盖配置项的环境变量前缀
+const ENV_PREFIX: &str = "XPP_";
 
 // 全局访问config
 pub static CONFIG: Lazy<Config> = Lazy::new(|| init().unwrap());
 
-#[derive(Debug, Deserialize, Serialize)]
+// 未配置的项使用默认值，配置文件可以只写需要修改的部分
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
 pub struct Config {
     pub server: Server,
     pub logger: Logger,
     pub proxy: Proxy,
+    pub api: Api,
+    pub route: Route,
+    pub geoip: Geoip,
+    pub limit: Limit,
+    pub provider: Vec<Provider>,
+    pub traffic: Traffic,
+    pub timeout: Timeout,
+    pub access_log: AccessLog,
+    pub listener: Vec<Listener>,
+    pub user: Vec<User>,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
+#[serde(default)]
 pub struct Server {
     pub name: String,
     pub host: String,
     pub port: u16,
+    // 允许连接的客户端网段，为空时允许所有客户端
+    pub allow: Vec<IpNet>,
+    // 拒绝连接的客户端网段，优先于 allow
+    pub deny: Vec<IpNet>,
+}
+
+impl Default for Server {
+    fn default() -> Self {
+        Server {
+            name: "proxy_pool".to_string(),
+            host: "127.0.0.1".to_string(),
+            port: 9000,
+            allow: Vec::new(),
+            deny: Vec::new(),
+        }
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize)]
+#[serde(default)]
 pub struct Logger {
+    // 日志级别，例如 "info"，也可以写完整的过滤规则，例如 "warn,x_proxy_pool=debug"
+    // 设置了 RUST_LOG 环境变量时以环境变量为准
     pub level: String,
+    // 输出格式
+    pub format: LogFormat,
+    // 日志文件路径，未配置时输出到终端
+    pub file: Option<String>,
+    // 文件超过该大小时轮转，例如 "100MB"
+    pub max_size: Option<String>,
+    // 按时间轮转
+    pub rotation: Rotation,
+    // 保留的历史文件数量，为 0 时全部保留
+    pub keep: usize,
+}
+
+impl Default for Logger {
+    fn default() -> Self {
+        Logger {
+            level: "info".to_string(),
+            format: LogFormat::Full,
+            file: None,
+            max_size: None,
+            rotation: Rotation::Never,
+            keep: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Full,
+    Pretty,
+    Compact,
+    Json,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
+#[serde(default)]
 pub struct Proxy {
     pub proxy_file: String,
     pub timeout: usize,
@@ -35,62 +120,485 @@ pub struct Proxy {
     pub auto_switch: bool,
     pub auto_switch_interval: usize,
     pub max_test_count: usize,
+    // 代理文件中所有代理的默认标签
+    pub tags: BTreeMap<String, String>,
+    // 粘性会话的默认有效期（秒）
+    pub session_ttl: u64,
+    // 用户名参数中 rotate 指定的会话有效期上限（秒）
+    pub max_session_ttl: u64,
+    // 所有代理的连接数都已满时排队等待的时间（毫秒），未配置时直接失败
+    pub queue_timeout: Option<u64>,
+    // 客户端报告上游故障后的冷却时间（秒）
+    pub penalty: u64,
+    // 检测匿名级别的判断页面，需要回显来源 IP 和请求头，未配置时不检测
+    pub judge_url: Option<String>,
+}
+
+impl Default for Proxy {
+    fn default() -> Self {
+        Proxy {
+            proxy_file: "proxy.txt".to_string(),
+            timeout: 3000,
+            health_check_interval: 60,
+            retry_count: 3,
+            auto_switch: true,
+            auto_switch_interval: 300,
+            max_test_count: 10,
+            tags: BTreeMap::new(),
+            session_ttl: 600,
+            max_session_ttl: 86400,
+            queue_timeout: None,
+            penalty: 300,
+            judge_url: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct Route {
+    // 路由规则文件路径
+    pub rule_file: Option<String>,
+}
+
+// 服务商，匹配选择器的代理共享并发连接数上限
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Provider {
+    pub name: String,
+    // 上游标签选择器，例如 "provider=example"
+    pub selector: String,
+    pub max_connections: usize,
+    // 每个统计周期的流量配额，例如 "500GB"
+    pub quota: Option<String>,
+}
+
+// 流量统计
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Traffic {
+    // 流量统计的保存文件，重启后继续累计
+    pub file: String,
+    // 统计周期，配额在每个周期开始时重置
+    pub period: TrafficPeriod,
+    // 写入文件的间隔（秒）
+    pub save_interval: u64,
+}
+
+impl Default for Traffic {
+    fn default() -> Self {
+        Traffic {
+            file: "traffic.json".to_string(),
+            period: TrafficPeriod::Monthly,
+            save_interval: 60,
+        }
+    }
+}
+
+// 连接超时（秒），为 0 时不限制
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Timeout {
+    // 从接受连接到与目标建立隧道的最长时间
+    pub handshake: u64,
+    // 隧道两个方向都没有数据的最长时间
+    pub idle: u64,
+    // 连接的最长存活时间
+    pub max_lifetime: u64,
+    // 关闭服务时等待已有连接结束的最长时间
+    pub shutdown: u64,
+}
+
+impl Default for Timeout {
+    fn default() -> Self {
+        Timeout {
+            handshake: 10,
+            idle: 300,
+            max_lifetime: 0,
+            shutdown: 30,
+        }
+    }
+}
+
+// 隧道访问日志，每行一条 JSON 记录
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct AccessLog {
+    // 日志文件路径，未配置时不记录
+    pub file: Option<String>,
+    // 文件超过该大小时轮转，例如 "100MB"
+    pub max_size: Option<String>,
+    // 按时间轮转
+    pub rotation: Rotation,
+    // 保留的历史文件数量，为 0 时全部保留
+    pub keep: usize,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Rotation {
+    #[default]
+    Never,
+    Hourly,
+    Daily,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TrafficPeriod {
+    Daily,
+    #[default]
+    Monthly,
+}
+
+// 单个客户端的连接限制，未认证时按客户端 IP 计算，认证后按用户计算
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct Limit {
+    // 每秒允许的新连接数
+    pub rate: Option<f64>,
+    // 允许的突发连接数，默认与 rate 相同
+    pub burst: Option<u32>,
+    // 最大并发连接数
+    pub max_connections: Option<usize>,
+    // 单个隧道每个方向每秒的带宽，例如 "1MB"
+    pub bandwidth: Option<String>,
+    // 同一用户所有隧道合计每个方向每秒的带宽
+    pub user_bandwidth: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct Geoip {
+    // MaxMind 格式的城市数据库路径，例如 GeoLite2-City.mmdb
+    pub city_db: Option<String>,
+    // MaxMind 格式的 ASN 数据库路径，例如 GeoLite2-ASN.mmdb
+    pub asn_db: Option<String>,
 }
 
-impl Default for Config {
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Api {
+    pub enable: bool,
+    pub host: String,
+    pub port: u16,
+    // 访问令牌，请求需携带 Authorization: Bearer <token>，启用 API 时必须配置
+    pub token: Option<String>,
+    // 客户端令牌，只能调用获取上游和报告故障的接口，管理令牌同样可以调用
+    pub client_token: Option<String>,
+    // 作为指标 tag 维度的上游标签，只应列出取值有限的标签，例如 country type
+    pub metric_tags: Vec<String>,
+}
+
+impl Default for Api {
     fn default() -> Self {
-        Config {
-            server: Server {
-                name: "proxy_pool".to_string(),
-                host: "127.0.0.1".to_string(),
-                port: 9000,
-            },
-            logger: Logger {
-                level: "info".to_string(),
-            },
-            proxy: Proxy {
-                proxy_file: "proxy.txt".to_string(),
-                timeout: 3000,
-                health_check_interval: 60,
-                retry_count: 3,
-                auto_switch: true,
-                auto_switch_interval: 300,
-                max_test_count: 10,
-            },
+        Api {
+            enable: false,
+            host: "127.0.0.1".to_string(),
+            port: 9100,
+            token: None,
+            client_token: None,
+            metric_tags: vec!["group".to_string()],
         }
     }
 }
 
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Listener {
+    pub name: String,
+    #[serde(default)]
+    pub host: String,
+    #[serde(default)]
+    pub port: u16,
+    // IPv6 地址是否只接受 IPv6 连接，默认双栈
+    #[serde(default)]
+    pub ipv6_only: bool,
+    // Unix 套接字路径，设置后忽略 host 和 port
+    pub path: Option<String>,
+    // Unix 套接字文件权限，例如 "660"
+    pub permissions: Option<String>,
+    // 监听模式
+    #[serde(default)]
+    pub mode: ListenerMode,
+    // 端口映射模式下的结束端口，监听 port..=port_end
+    pub port_end: Option<u16>,
+    // 透明代理模式下使用 TPROXY，否则使用 REDIRECT
+    #[serde(default)]
+    pub tproxy: bool,
+    // 入站连接携带 PROXY 协议头 (v1/v2)
+    #[serde(default)]
+    pub proxy_protocol: bool,
+    // 允许的入站协议
+    #[serde(default)]
+    pub protocol: ListenerProtocol,
+    // 认证方式
+    #[serde(default)]
+    pub auth: ListenerAuth,
+    // 使用的代理分组，为空时使用全部代理
+    pub group: Option<String>,
+    // 上游标签选择器，例如 "country=DE,type=residential"
+    pub selector: Option<String>,
+    // 解析用户名中携带的路由参数，例如 alice-session-abc123-country-us
+    #[serde(default)]
+    pub username_params: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ListenerMode {
+    // 每个连接从代理池中轮询选取上游
+    #[default]
+    Proxy,
+    // 每个本地端口固定映射到一个上游
+    PortMap,
+    // 透明代理，接收 iptables 重定向的连接
+    Transparent,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ListenerProtocol {
+    #[default]
+    Auto,
+    Http,
+    Socks5,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ListenerAuth {
+    #[default]
+    None,
+    Password,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct User {
+    pub username: String,
+    pub password: String,
+    // 该用户使用的上游标签选择器
+    pub selector: Option<String>,
+    // 该用户的连接限制，覆盖 [limit] 中的同名配置
+    #[serde(flatten)]
+    pub limit: Limit,
+    // 每个统计周期的流量配额，例如 "10GB"
+    pub quota: Option<String>,
+}
+
+impl Config {
+    // 未配置 [[listener]] 时，使用 [server] 作为唯一的监听器
+    pub fn listeners(&self) -> Vec<Listener> {
+        if !self.listener.is_empty() {
+            return self.listener.clone();
+        }
+        vec![Listener {
+            name: self.server.name.clone(),
+            host: self.server.host.clone(),
+            port: self.server.port,
+            ipv6_only: false,
+            path: None,
+            permissions: None,
+            mode: ListenerMode::Proxy,
+            port_end: None,
+            tproxy: false,
+            proxy_protocol: false,
+            protocol: ListenerProtocol::Auto,
+            auth: ListenerAuth::None,
+            group: None,
+            selector: None,
+            username_params: false,
+        }]
+    }
+}
+
+// 设置配置文件路径，需要在首次访问 CONFIG 之前调用
+pub fn set_path(path: &str) {
+    _ = CONFIG_PATH.set(path.to_string());
+}
+
+pub fn path() -> &'static str {
+    CONFIG_PATH
+        .get()
+        .map(String::as_str)
+        .unwrap_or(DEFAULT_CONFIG_PATH)
+}
+
+// 设置命令行中的配置项，格式为 key=value，需要在首次访问 CONFIG 之前调用
+pub fn set_overrides(overrides: Vec<String>) {
+    _ = CONFIG_OVERRIDES.set(overrides);
+}
+
 pub fn init() -> Result<Config> {
-    // 配置文件路径
-    let config_path = Path::new("config.toml");
+    if let Some(config) = LOADED.lock().unwrap().take() {
+        return Ok(config);
+    }
+    read()
+}
+
+// 读取并校验配置，有问题时返回包含全部问题的错误，需要在首次访问 CONFIG 之前调用
+// serving 为 false 时用于 check / import / export，不要求代理文件已经存在
+pub fn load(serving: bool) -> Result<()> {
+    let config = read()?;
+    let problems = validate(&config, serving);
+    if !problems.is_empty() {
+        return Err(anyhow::anyhow!(
+            "配置 {} 存在 {} 个问题:\n  {}",
+            path(),
+            problems.len(),
+            problems.join("\n  ")
+        ));
+    }
+    *LOADED.lock().unwrap() = Some(config);
+    Lazy::force(&CONFIG);
+    Ok(())
+}
+
+// 依次合并默认值、配置文件、XPP_ 环境变量和命令行参数，重新加载配置时也使用
+pub fn read() -> Result<Config> {
+    let mut value = match fs::read_to_string(path()) {
+        Ok(content) => toml::from_str(&content)
+            .map_err(|e| anyhow::anyhow!("解析配置文件 {} 失败: {}", path(), e))?,
+        // 未指定配置文件且默认配置文件不存在时，只使用默认值
+        Err(e) if e.kind() == ErrorKind::NotFound && CONFIG_PATH.get().is_none() => {
+            Value::Table(Table::new())
+        }
+        Err(e) => return Err(anyhow::anyhow!("读取配置文件 {} 失败: {}", path(), e)),
+    };
+
+    // 环境变量 XPP_PROXY__MAX_TEST_COUNT 对应 proxy.max_test_count
+    let mut vars: Vec<(String, String)> = std::env::vars()
+        .filter(|(name, _)| name.starts_with(ENV_PREFIX))
+        .collect();
+    vars.sort();
+    for (name, raw) in vars {
+        let key = name[ENV_PREFIX.len()..].to_lowercase().replace("__", ".");
+        apply(&mut value, &key, &raw)
+            .map_err(|e| anyhow::anyhow!("环境变量 {} 无效: {}", name, e))?;
+    }
+
+    for item in CONFIG_OVERRIDES.get().into_iter().flatten() {
+        let (key, raw) = item
+            .split_once('=')
+            .ok_or_else(|| anyhow::anyhow!("配置项 {} 无效，格式应为 key=value", item))?;
+        apply(&mut value, key.trim(), raw)
+            .map_err(|e| anyhow::anyhow!("配置项 {} 无效: {}", item, e))?;
+    }
+
+    value
+        .try_into()
+        .map_err(|e| anyhow::anyhow!("配置无效: {}", e))
+}
 
-    // 配置文件不存在时，创建默认配置文件
-    if !config_path.exists() {
-        fs::write(config_path, DEFAULT_CONFIG)?;
+// 将 raw 写入 key 对应的位置，key 用 . 分隔，数组使用下标，例如 listener.0.port
+// raw 按 TOML 值解析，解析失败或与配置项的类型不符时作为字符串，例如 server.name=2024
+fn apply(value: &mut Value, key: &str, raw: &str) -> Result<()> {
+    let parsed = toml::from_str::<Table>(&format!("value = {}", raw))
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+        .unwrap_or_else(|| Value::String(raw.to_string()));
+    if parsed.is_str() {
+        return set(value, key, parsed);
     }
 
-    // 读取配置文件内容并解析为 Config 结构体
-    let content = fs::read_to_string(config_path)?;
-    let config: Config = toml::from_str(&content)?;
+    let mut typed = value.clone();
+    set(&mut typed, key, parsed)?;
+    if typed.clone().try_into::<Config>().is_err() {
+        let mut string = value.clone();
+        set(&mut string, key, Value::String(raw.to_string()))?;
+        // 作为字符串也无效时保留解析出的值，由最终的检查报告错误
+        if string.clone().try_into::<Config>().is_ok() {
+            *value = string;
+            return Ok(());
+        }
+    }
+    *value = typed;
+    Ok(())
+}
 
-    Ok(config)
+fn set(value: &mut Value, key: &str, parsed: Value) -> Result<()> {
+    let mut current = value;
+    let mut parts = key.split('.').peekable();
+    while let Some(part) = parts.next() {
+        if part.is_empty() {
+            return Err(anyhow::anyhow!("配置项名称为空"));
+        }
+        let last = parts.peek().is_none();
+        current = match current {
+            Value::Table(table) => {
+                if last {
+                    table.insert(part.to_string(), parsed);
+                    return Ok(());
+                }
+                table
+                    .entry(part)
+                    .or_insert_with(|| Value::Table(Table::new()))
+            }
+            Value::Array(array) => {
+                let index: usize = part
+                    .parse()
+                    .map_err(|_| anyhow::anyhow!("{} 不是数组下标", part))?;
+                let len = array.len();
+                let item = array
+                    .get_mut(index)
+                    .ok_or_else(|| anyhow::anyhow!("下标 {} 超出范围，共 {} 项", index, len))?;
+                if last {
+                    *item = parsed;
+                    return Ok(());
+                }
+                item
+            }
+            _ => return Err(anyhow::anyhow!("{} 不是表或数组", part)),
+        };
+    }
+    Ok(())
 }
 
-const DEFAULT_CONFIG: &str = r#"[server]
-name = "proxy_pool"
-host = "127.0.0.1"
-port = 9000
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-[logger]
-level = "info"
+    fn config(overrides: &[(&str, &str)]) -> Result<Config> {
+        let mut value = Value::Table(Table::new());
+        for (key, raw) in overrides {
+            apply(&mut value, key, raw)?;
+        }
+        Ok(value.try_into()?)
+    }
 
-[proxy]
-proxy_file = "proxy.txt"
-timeout = 3000
-health_check_interval = 60
-retry_count = 3
-auto_switch = true
-auto_switch_interval = 300
-max_test_count = 10
-"#;
+    #[test]
+    fn typed_values() {
+        let config = config(&[
+            ("server.port", "9001"),
+            ("proxy.auto_switch", "true"),
+            ("server.deny", "[\"10.0.0.0/8\"]"),
+        ])
+        .unwrap();
+        assert_eq!(config.server.port, 9001);
+        assert!(config.proxy.auto_switch);
+        assert_eq!(
+            config.server.deny,
+            vec!["10.0.0.0/8".parse::<IpNet>().unwrap()]
+        );
+    }
+
+    #[test]
+    fn strings_that_look_typed() {
+        let config = config(&[
+            ("server.name", "2024"),
+            ("logger.level", "true"),
+            ("api.token", "123456"),
+            ("proxy.proxy_file", "list.txt"),
+            ("api.client_token", "\"quoted\""),
+        ])
+        .unwrap();
+        assert_eq!(config.server.name, "2024");
+        assert_eq!(config.logger.level, "true");
+        assert_eq!(config.api.token.as_deref(), Some("123456"));
+        assert_eq!(config.proxy.proxy_file, "list.txt");
+        assert_eq!(config.api.client_token.as_deref(), Some("quoted"));
+    }
+
+    #[test]
+    fn invalid_values() {
+        assert!(config(&[("server.port", "abc")]).is_err());
+        assert!(config(&[("server.port", "70000")]).is_err());
+        assert!(config(&[("listener.0.port", "9000")]).is_err());
+        assert!(config(&[("server..port", "9000")]).is_err());
+    }
+}
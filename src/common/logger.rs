@@ -1,19 +1,91 @@
+use std::sync::Mutex;
+
 use anyhow::Result;
+use once_cell::sync::OnceCell;
+use tracing::{Subscriber, info};
+use tracing_subscriber::{
+    EnvFilter, Layer, Registry,
+    fmt::{self, writer::BoxMakeWriter},
+    layer::SubscriberExt,
+    registry::LookupSpan,
+    reload,
+    util::SubscriberInitExt,
+};
+
+use crate::{
+    common::config::{self, CONFIG, LogFormat},
+    util::{RotatingFile, parse_size},
+};
 
-use super::config::CONFIG;
+// 用于运行时修改日志级别
+static FILTER: OnceCell<reload::Handle<EnvFilter, Registry>> = OnceCell::new();
 
 pub fn init() -> Result<()> {
-    // if std::env::var_os("RUST_LOG").is_none() {
-    //     let app_name =
-    //         std::env::var("CARGO_PKG_NAME").unwrap_or_else(|_| CONFIG.server.name.clone());
-    //     let level = CONFIG.logger.level.as_str();
-    //     let env = format!("{app_name}={level}");
-    //     unsafe {
-    //         std::env::set_var("RUST_LOG", env);
-    //     }
-    // }
-    tracing_subscriber::fmt()
-        // .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
-        .init();
+    let logger = &CONFIG.logger;
+
+    // RUST_LOG 环境变量优先于配置文件
+    let filter = match EnvFilter::try_from_default_env() {
+        Ok(filter) => filter,
+        Err(_) => EnvFilter::try_new(directives(&logger.level))?,
+    };
+    let (filter, handle) = reload::Layer::new(filter);
+
+    let layer = match &logger.file {
+        Some(path) => {
+            let max_size = logger.max_size.as_deref().map(parse_size).transpose()?;
+            let file = RotatingFile::open(path, max_size, logger.rotation, logger.keep)?;
+            output(logger.format, BoxMakeWriter::new(Mutex::new(file)), false)
+        }
+        None => output(logger.format, BoxMakeWriter::new(std::io::stdout), true),
+    };
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(layer)
+        .try_init()?;
+    _ = FILTER.set(handle);
+    Ok(())
+}
+
+// 重新读取配置文件中的日志级别，设置了 RUST_LOG 时保持不变
+pub fn reload() -> Result<()> {
+    if std::env::var_os("RUST_LOG").is_some() {
+        info!("已设置 RUST_LOG，忽略配置文件中的日志级别");
+        return Ok(());
+    }
+    let config = config::read()?;
+    let filter = EnvFilter::try_new(directives(&config.logger.level))?;
+    FILTER
+        .get()
+        .ok_or_else(|| anyhow::anyhow!("日志记录未初始化"))?
+        .reload(filter)?;
+    info!("日志级别已更新为: {}", config.logger.level);
     Ok(())
 }
+
+// 只写级别时作用于本项目，其他依赖只输出警告以上的日志
+fn directives(level: &str) -> String {
+    if level.contains('=') || level.contains(',') {
+        level.to_string()
+    } else {
+        format!("warn,{}={}", env!("CARGO_CRATE_NAME"), level)
+    }
+}
+
+// 按配置的格式输出日志
+fn output<S>(
+    format: LogFormat,
+    writer: BoxMakeWriter,
+    ansi: bool,
+) -> Box<dyn Layer<S> + Send + Sync>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    let layer = fmt::layer().with_writer(writer).with_ansi(ansi);
+    match format {
+        LogFormat::Full => layer.boxed(),
+        LogFormat::Pretty => layer.pretty().boxed(),
+        LogFormat::Compact => layer.compact().boxed(),
+        LogFormat::Json => layer.json().boxed(),
+    }
+}
@@ -2,6 +2,7 @@ use anyhow::Result;
 
 pub mod config;
 pub mod logger;
+pub mod validate;
 
 pub fn init() -> Result<()> {
     logger::init()?;
@@ -0,0 +1,269 @@
+use std::{collections::HashSet, path::Path};
+
+use tracing_subscriber::EnvFilter;
+
+use crate::{
+    common::config::{Config, Limit, ListenerAuth, ListenerMode},
+    proxy::selector::Selector,
+    util::parse_size,
+};
+
+// 检查配置的取值范围、引用的文件以及配置项之间的约束，返回全部问题，每项以配置路径开头
+// serving 为 true 时按启动服务检查，代理文件必须存在，否则只要求其所在目录存在
+pub fn validate(config: &Config, serving: bool) -> Vec<String> {
+    let mut problems = Problems::default();
+
+    // [logger] [access_log]
+    if let Err(e) = EnvFilter::try_new(&config.logger.level) {
+        problems.add("logger.level", format!("无效的日志级别: {}", e));
+    }
+    problems.size("logger.max_size", config.logger.max_size.as_ref());
+    problems.parent("logger.file", config.logger.file.as_ref());
+    problems.size("access_log.max_size", config.access_log.max_size.as_ref());
+    problems.parent("access_log.file", config.access_log.file.as_ref());
+
+    // [proxy]
+    let proxy = &config.proxy;
+    if serving {
+        problems.file("proxy.proxy_file", Some(&proxy.proxy_file));
+    } else {
+        problems.parent("proxy.proxy_file", Some(&proxy.proxy_file));
+    }
+    problems.positive("proxy.timeout", proxy.timeout as u64);
+    problems.positive("proxy.max_test_count", proxy.max_test_count as u64);
+    problems.positive("proxy.max_session_ttl", proxy.max_session_ttl);
+    problems.positive(
+        "proxy.health_check_interval",
+        proxy.health_check_interval as u64,
+    );
+    // https 页面经过隧道访问，看不到上游添加的请求头
+    if let Some(url) = &proxy.judge_url
+        && !url.starts_with("http://")
+    {
+        problems.add("proxy.judge_url", "判断页面需要使用 http:// 地址");
+    }
+    if proxy.auto_switch {
+        problems.positive(
+            "proxy.auto_switch_interval",
+            proxy.auto_switch_interval as u64,
+        );
+    }
+
+    // [route] [geoip] [traffic]
+    problems.file("route.rule_file", config.route.rule_file.as_ref());
+    problems.file("geoip.city_db", config.geoip.city_db.as_ref());
+    problems.file("geoip.asn_db", config.geoip.asn_db.as_ref());
+    problems.parent("traffic.file", Some(&config.traffic.file));
+    problems.positive("traffic.save_interval", config.traffic.save_interval);
+
+    // [api]
+    if config.api.enable && config.api.port == 0 {
+        problems.add("api.port", "启用 API 时必须配置端口");
+    }
+    match &config.api.token {
+        Some(token) if token.is_empty() => problems.add("api.token", "不能为空字符串"),
+        None if config.api.enable => problems.add("api.token", "启用 API 时必须配置访问令牌"),
+        _ => {}
+    }
+    match &config.api.client_token {
+        Some(token) if token.is_empty() => problems.add("api.client_token", "不能为空字符串"),
+        Some(token) if config.api.token.as_ref() == Some(token) => {
+            problems.add("api.client_token", "不能与 api.token 相同")
+        }
+        _ => {}
+    }
+    // 每个上游各不相同的检测结果作为维度会让时间序列随上游数量增长
+    for tag in &config.api.metric_tags {
+        if tag == "latency" || tag == "egress" {
+            problems.add("api.metric_tags", format!("{} 不能作为指标维度", tag));
+        }
+    }
+
+    problems.limit("limit", &config.limit);
+
+    // [[provider]]
+    let mut names = HashSet::new();
+    for (i, provider) in config.provider.iter().enumerate() {
+        let key = format!("provider.{}", i);
+        if !names.insert(&provider.name) {
+            problems.add(
+                &format!("{}.name", key),
+                format!("名称 {} 重复", provider.name),
+            );
+        }
+        problems.selector(&format!("{}.selector", key), Some(&provider.selector));
+        problems.positive(
+            &format!("{}.max_connections", key),
+            provider.max_connections as u64,
+        );
+        problems.size(&format!("{}.quota", key), provider.quota.as_ref());
+    }
+
+    // [[user]]
+    let mut usernames = HashSet::new();
+    for (i, user) in config.user.iter().enumerate() {
+        let key = format!("user.{}", i);
+        if user.username.is_empty() {
+            problems.add(&format!("{}.username", key), "不能为空");
+        } else if !usernames.insert(&user.username) {
+            problems.add(
+                &format!("{}.username", key),
+                format!("用户 {} 重复", user.username),
+            );
+        }
+        problems.selector(&format!("{}.selector", key), user.selector.as_ref());
+        problems.limit(&key, &user.limit);
+        problems.size(&format!("{}.quota", key), user.quota.as_ref());
+    }
+
+    // [[listener]]，未配置时检查作为监听器的 [server]
+    let mut names = HashSet::new();
+    let mut bound: Vec<(String, &str, u16, u16)> = Vec::new();
+    let listeners = config.listeners();
+    for (i, listener) in listeners.iter().enumerate() {
+        let key = if config.listener.is_empty() {
+            "server".to_string()
+        } else {
+            format!("listener.{}", i)
+        };
+        if listener.name.is_empty() {
+            problems.add(&format!("{}.name", key), "不能为空");
+        } else if !names.insert(&listener.name) {
+            problems.add(
+                &format!("{}.name", key),
+                format!("名称 {} 重复", listener.name),
+            );
+        }
+
+        if listener.path.is_some() {
+            problems.parent(&format!("{}.path", key), listener.path.as_ref());
+            if let Some(permissions) = &listener.permissions
+                && u32::from_str_radix(permissions, 8).is_err()
+            {
+                problems.add(
+                    &format!("{}.permissions", key),
+                    format!(
+                        "无效的套接字权限: {}，应为八进制，例如 \"660\"",
+                        permissions
+                    ),
+                );
+            }
+        } else {
+            if listener.host.is_empty() {
+                problems.add(&format!("{}.host", key), "未配置监听地址，或配置 path");
+            }
+            if listener.port == 0 {
+                problems.add(&format!("{}.port", key), "未配置监听端口，或配置 path");
+            }
+            let end = match listener.port_end {
+                Some(end) if listener.mode == ListenerMode::PortMap => {
+                    if end < listener.port {
+                        problems.add(
+                            &format!("{}.port_end", key),
+                            format!("不能小于 port ({})", listener.port),
+                        );
+                    }
+                    end
+                }
+                _ => listener.port,
+            };
+            if listener.port != 0 {
+                if let Some((other, ..)) = bound.iter().find(|(_, host, start, stop)| {
+                    *host == listener.host && listener.port <= *stop && end >= *start
+                }) {
+                    problems.add(
+                        &format!("{}.port", key),
+                        format!("与 {} 监听的端口重叠", other),
+                    );
+                }
+                bound.push((key.clone(), &listener.host, listener.port, end));
+            }
+        }
+
+        if listener.auth == ListenerAuth::Password && config.user.is_empty() {
+            problems.add(&format!("{}.auth", key), "使用密码认证时需要配置 [[user]]");
+        }
+        if listener.username_params && listener.auth != ListenerAuth::Password {
+            problems.add(
+                &format!("{}.username_params", key),
+                "需要同时配置 auth = \"password\"",
+            );
+        }
+        problems.selector(&format!("{}.selector", key), listener.selector.as_ref());
+    }
+
+    problems.0
+}
+
+#[derive(Default)]
+struct Problems(Vec<String>);
+
+impl Problems {
+    fn add(&mut self, key: &str, message: impl ToString) {
+        self.0.push(format!("{}: {}", key, message.to_string()));
+    }
+
+    fn positive(&mut self, key: &str, value: u64) {
+        if value == 0 {
+            self.add(key, "必须大于 0");
+        }
+    }
+
+    // 引用的文件必须存在
+    fn file(&mut self, key: &str, path: Option<&String>) {
+        if let Some(path) = path
+            && !Path::new(path).exists()
+        {
+            self.add(key, format!("文件不存在: {}", path));
+        }
+    }
+
+    // 写入的文件所在目录必须存在
+    fn parent(&mut self, key: &str, path: Option<&String>) {
+        if let Some(path) = path
+            && let Some(parent) = Path::new(path).parent()
+            && !parent.as_os_str().is_empty()
+            && !parent.is_dir()
+        {
+            self.add(key, format!("目录不存在: {}", parent.display()));
+        }
+    }
+
+    fn size(&mut self, key: &str, size: Option<&String>) {
+        match size.map(|size| parse_size(size)) {
+            Some(Ok(0)) => self.add(key, "必须大于 0"),
+            Some(Err(e)) => self.add(key, format!("{}，例如 \"500MB\"", e)),
+            _ => {}
+        }
+    }
+
+    fn selector(&mut self, key: &str, selector: Option<&String>) {
+        if let Some(selector) = selector
+            && let Err(e) = Selector::from(selector)
+        {
+            self.add(key, e);
+        }
+    }
+
+    fn limit(&mut self, prefix: &str, limit: &Limit) {
+        if let Some(rate) = limit.rate
+            && !(rate > 0.0 && rate.is_finite())
+        {
+            self.add(&format!("{}.rate", prefix), "必须大于 0");
+        }
+        if let Some(burst) = limit.burst {
+            self.positive(&format!("{}.burst", prefix), burst as u64);
+        }
+        if let Some(max_connections) = limit.max_connections {
+            self.positive(
+                &format!("{}.max_connections", prefix),
+                max_connections as u64,
+            );
+        }
+        self.size(&format!("{}.bandwidth", prefix), limit.bandwidth.as_ref());
+        self.size(
+            &format!("{}.user_bandwidth", prefix),
+            limit.user_bandwidth.as_ref(),
+        );
+    }
+}
@@ -0,0 +1,12 @@
+use anyhow::Result;
+use model::GEOIP;
+use tracing::info;
+
+pub mod model;
+
+pub async fn init() -> Result<()> {
+    if GEOIP.load().await? {
+        info!("GeoIP 数据库加载成功");
+    }
+    Ok(())
+}
@@ -0,0 +1,178 @@
+use std::{net::IpAddr, sync::Arc};
+
+use anyhow::Result;
+use maxminddb::{Reader, geoip2};
+use once_cell::sync::Lazy;
+use tokio::sync::RwLock;
+
+use crate::{common::config::CONFIG, proxy::model::Proxy};
+
+// IP 地址的地理位置和 ASN 信息
+#[derive(Debug, Clone, Default)]
+pub struct GeoInfo {
+    pub country: Option<String>,
+    pub city: Option<String>,
+    pub asn: Option<u32>,
+}
+
+impl GeoInfo {
+    // 写入代理的补充标签，标签名带有前缀，例如 egress_country
+    // 每次都覆盖，数据库更新后随之刷新；代理文件中手动填写的同名标签在选择时优先
+    fn tag(&self, proxy: &mut Proxy, prefix: &str) {
+        let values = [
+            ("country", self.country.clone()),
+            ("city", self.city.clone()),
+            ("asn", self.asn.map(|asn| asn.to_string())),
+        ];
+        for (key, value) in values {
+            let key = format!("{}{}", prefix, key);
+            match value {
+                Some(value) => proxy.derived.insert(key, value),
+                None => proxy.derived.remove(&key),
+            };
+        }
+    }
+}
+
+// 本地 MaxMind 格式数据库，不需要访问网络
+pub struct GeoIp {
+    pub city: Arc<RwLock<Option<Reader<Vec<u8>>>>>,
+    pub asn: Arc<RwLock<Option<Reader<Vec<u8>>>>>,
+}
+
+impl Default for GeoIp {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl GeoIp {
+    pub fn new() -> Self {
+        Self {
+            city: Arc::new(RwLock::new(None)),
+            asn: Arc::new(RwLock::new(None)),
+        }
+    }
+
+    // 读取配置的数据库文件，未配置时返回 false
+    pub async fn load(&self) -> Result<bool> {
+        let open = |path: &Option<String>| -> Result<Option<Reader<Vec<u8>>>> {
+            match path {
+                Some(path) => Reader::open_readfile(path)
+                    .map(Some)
+                    .map_err(|e| anyhow::anyhow!("读取 GeoIP 数据库 {} 失败: {}", path, e)),
+                None => Ok(None),
+            }
+        };
+        let city = open(&CONFIG.geoip.city_db)?;
+        let asn = open(&CONFIG.geoip.asn_db)?;
+        let loaded = city.is_some() || asn.is_some();
+
+        *self.city.write().await = city;
+        *self.asn.write().await = asn;
+        Ok(loaded)
+    }
+
+    // 查询 IP 地址，数据库中没有记录的字段为空
+    pub async fn lookup(&self, ip: IpAddr) -> GeoInfo {
+        let mut info = GeoInfo::default();
+        if let Some(reader) = self.city.read().await.as_ref()
+            && let Ok(city) = reader.lookup::<geoip2::City>(ip)
+        {
+            info.country = city
+                .country
+                .and_then(|country| country.iso_code)
+                .map(str::to_string);
+            // 城市名中的空格替换为 _，避免破坏代理文件的列格式
+            info.city = city
+                .city
+                .and_then(|city| city.names)
+                .and_then(|names| names.get("en").map(|name| name.replace(' ', "_")));
+        }
+        if let Some(reader) = self.asn.read().await.as_ref()
+            && let Ok(asn) = reader.lookup::<geoip2::Asn>(ip)
+        {
+            info.asn = asn.autonomous_system_number;
+        }
+        info
+    }
+
+    // 根据代理地址和出口 IP 补充标签:
+    // 代理地址写入 country / city / asn，出口 IP 写入 egress_country / egress_city / egress_asn
+    pub async fn enrich(&self, proxy: &mut Proxy) {
+        if self.city.read().await.is_none() && self.asn.read().await.is_none() {
+            return;
+        }
+        if let Ok(ip) = proxy.host.parse::<IpAddr>() {
+            self.lookup(ip).await.tag(proxy, "");
+        }
+        if let Some(ip) = proxy
+            .derived
+            .get("egress")
+            .and_then(|egress| egress.parse::<IpAddr>().ok())
+        {
+            self.lookup(ip).await.tag(proxy, "egress_");
+        }
+    }
+}
+
+pub fn init() -> Result<Arc<GeoIp>> {
+    let geoip = Arc::new(GeoIp::new());
+    Ok(geoip)
+}
+
+// 全局访问 GeoIP 数据库
+pub static GEOIP: Lazy<Arc<GeoIp>> = Lazy::new(|| init().unwrap());
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::model::Protocol;
+
+    fn info(country: &str, city: Option<&str>) -> GeoInfo {
+        GeoInfo {
+            country: Some(country.to_string()),
+            city: city.map(str::to_string),
+            asn: Some(64500),
+        }
+    }
+
+    #[test]
+    fn tags_are_refreshed() {
+        let mut proxy = Proxy::new(Protocol::Socks5, "192.0.2.1".to_string(), 1080);
+        info("DE", Some("Berlin")).tag(&mut proxy, "");
+        assert_eq!(proxy.label("city"), Some("Berlin"));
+
+        // 数据库更新后覆盖旧值，没有记录的字段被移除
+        info("NL", None).tag(&mut proxy, "");
+        assert_eq!(proxy.label("country"), Some("NL"));
+        assert_eq!(proxy.derived.get("city"), None);
+        assert_eq!(proxy.label("asn"), Some("64500"));
+    }
+
+    #[test]
+    fn manual_tags_win_and_are_saved_alone() {
+        let mut proxy = Proxy::new(Protocol::Socks5, "192.0.2.1".to_string(), 1080);
+        proxy.tags.insert("country".to_string(), "US".to_string());
+        info("DE", None).tag(&mut proxy, "");
+        info("FR", None).tag(&mut proxy, "egress_");
+
+        assert_eq!(proxy.label("country"), Some("US"));
+        assert_eq!(proxy.label("egress_country"), Some("FR"));
+        assert_eq!(proxy.line(), "socks5://192.0.2.1:1080 country=US");
+    }
+
+    #[test]
+    fn manual_detected_keys_are_kept() {
+        let line = "socks5://192.0.2.1:1080 egress_country=US latency=50";
+        let mut proxy = Proxy::from_line(line).remove(0);
+        info("FR", None).tag(&mut proxy, "egress_");
+        proxy
+            .derived
+            .insert("latency".to_string(), "900".to_string());
+
+        assert_eq!(proxy.label("egress_country"), Some("US"));
+        assert_eq!(proxy.label("latency"), Some("50"));
+        assert_eq!(proxy.line(), line);
+    }
+}
@@ -1,4 +1,13 @@
+pub mod access;
+pub mod api;
+pub mod cli;
 pub mod common;
+pub mod geoip;
+pub mod limit;
+pub mod metrics;
 pub mod protocol;
 pub mod proxy;
+pub mod route;
+pub mod server;
+pub mod traffic;
 pub mod util;
@@ -0,0 +1,212 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use once_cell::sync::Lazy;
+use tracing::warn;
+
+use crate::common::config::Limit;
+
+use super::throttle;
+
+type SharedBucket = Arc<Mutex<throttle::Bucket>>;
+
+// 单个客户端的令牌桶和当前连接数，记录该客户端自己的速率和容量
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    active: usize,
+    rate: f64,
+    capacity: f64,
+}
+
+impl Bucket {
+    fn new(limit: &Limit, now: Instant) -> Self {
+        Bucket {
+            tokens: limit.burst(),
+            updated: now,
+            active: 0,
+            rate: limit.rate.unwrap_or_default(),
+            capacity: limit.burst(),
+        }
+    }
+
+    // 按经过的时间补充令牌
+    fn refill(&self, now: Instant) -> f64 {
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        (self.tokens + elapsed * self.rate).min(self.capacity)
+    }
+
+    // 没有连接且令牌已满时可以清理
+    fn idle(&self, now: Instant) -> bool {
+        self.active == 0 && self.refill(now) >= self.capacity
+    }
+}
+
+// 按客户端 IP 或认证用户限制新连接速率和并发连接数
+pub struct Limiter {
+    buckets: Arc<Mutex<HashMap<String, Bucket>>>,
+    // 每个用户读写两个方向共享的带宽令牌桶
+    bandwidth: Arc<Mutex<HashMap<String, (SharedBucket, SharedBucket)>>>,
+}
+
+// 连接许可，释放时减少客户端的并发连接数
+#[derive(Debug)]
+pub struct Permit {
+    key: String,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        let mut buckets = LIMITER.buckets.lock().unwrap();
+        if let Some(bucket) = buckets.get_mut(&self.key) {
+            bucket.active = bucket.active.saturating_sub(1);
+        }
+    }
+}
+
+impl Default for Limiter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Limiter {
+    pub fn new() -> Self {
+        Self {
+            buckets: Arc::new(Mutex::new(HashMap::new())),
+            bandwidth: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    // 用户共享的带宽令牌桶
+    pub fn bandwidth(&self, user: &str, rate: u64) -> (SharedBucket, SharedBucket) {
+        let mut bandwidth = self.bandwidth.lock().unwrap();
+        bandwidth
+            .entry(user.to_string())
+            .or_insert_with(|| (throttle::Bucket::new(rate), throttle::Bucket::new(rate)))
+            .clone()
+    }
+
+    // 获取连接许可，超过速率或并发限制时返回 None
+    pub fn acquire(&self, key: &str, limit: &Limit) -> Option<Permit> {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+
+        // 清理空闲且令牌已满的客户端，避免占用内存
+        if buckets.len() > 1024 {
+            buckets.retain(|_, bucket| !bucket.idle(now));
+        }
+
+        let bucket = buckets
+            .entry(key.to_string())
+            .or_insert_with(|| Bucket::new(limit, now));
+        bucket.rate = limit.rate.unwrap_or_default();
+        bucket.capacity = limit.burst();
+
+        if let Some(max_connections) = limit.max_connections
+            && bucket.active >= max_connections
+        {
+            warn!("客户端 {} 超过并发连接数限制: {}", key, max_connections);
+            return None;
+        }
+
+        if limit.rate.is_some() {
+            bucket.tokens = bucket.refill(now);
+            bucket.updated = now;
+            if bucket.tokens < 1.0 {
+                warn!("客户端 {} 超过新连接速率限制", key);
+                return None;
+            }
+            bucket.tokens -= 1.0;
+        }
+
+        bucket.active += 1;
+        Some(Permit {
+            key: key.to_string(),
+        })
+    }
+}
+
+impl Limit {
+    pub fn is_empty(&self) -> bool {
+        self.rate.is_none() && self.max_connections.is_none()
+    }
+
+    // 合并另一个限制，other 中已配置的项优先
+    pub fn merge(&self, other: &Limit) -> Limit {
+        Limit {
+            rate: other.rate.or(self.rate),
+            burst: other.burst.or(self.burst),
+            max_connections: other.max_connections.or(self.max_connections),
+            bandwidth: other.bandwidth.clone().or(self.bandwidth.clone()),
+            user_bandwidth: other.user_bandwidth.clone().or(self.user_bandwidth.clone()),
+        }
+    }
+
+    // 令牌桶容量，未配置时为每秒速率
+    fn burst(&self) -> f64 {
+        match (self.burst, self.rate) {
+            (Some(burst), _) => burst as f64,
+            (None, Some(rate)) => rate.ceil().max(1.0),
+            (None, None) => 0.0,
+        }
+    }
+}
+
+pub fn init() -> anyhow::Result<Arc<Limiter>> {
+    let limiter = Arc::new(Limiter::new());
+    Ok(limiter)
+}
+
+// 全局访问连接限制
+pub static LIMITER: Lazy<Arc<Limiter>> = Lazy::new(|| init().unwrap());
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn limit(rate: f64, burst: u32) -> Limit {
+        Limit {
+            rate: Some(rate),
+            burst: Some(burst),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn idle_uses_own_limit() {
+        let start = Instant::now();
+        // 每秒 1 个、容量 10 的桶用掉 5 个令牌，2 秒后仍未补满
+        let mut slow = Bucket::new(&limit(1.0, 10), start);
+        slow.tokens -= 5.0;
+        // 每秒 100 个的桶同样用掉 5 个令牌，2 秒后已经补满
+        let mut fast = Bucket::new(&limit(100.0, 10), start);
+        fast.tokens -= 5.0;
+
+        let now = start + Duration::from_secs(2);
+        assert!(!slow.idle(now));
+        assert!(fast.idle(now));
+
+        fast.active = 1;
+        assert!(!fast.idle(now));
+    }
+
+    #[test]
+    fn limits_per_key() {
+        let limiter = Limiter::new();
+        let strict = limit(0.001, 1);
+        let loose = limit(1000.0, 100);
+        let first = limiter.acquire("strict", &strict);
+        assert!(first.is_some());
+        assert!(limiter.acquire("strict", &strict).is_none());
+        for _ in 0..50 {
+            assert!(limiter.acquire("loose", &loose).is_some());
+        }
+    }
+}
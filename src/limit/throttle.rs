@@ -0,0 +1,186 @@
+use std::{
+    future::Future,
+    io,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context as TaskContext, Poll, ready},
+    time::{Duration, Instant},
+};
+
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    time::Sleep,
+};
+
+use crate::{protocol::model::Context, util::parse_size};
+
+use super::model::LIMITER;
+
+// 带宽令牌桶，容量为一秒的字节数
+#[derive(Debug)]
+pub struct Bucket {
+    rate: f64,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    pub fn new(rate: u64) -> Arc<Mutex<Bucket>> {
+        Arc::new(Mutex::new(Bucket {
+            rate: rate as f64,
+            tokens: rate as f64,
+            updated: Instant::now(),
+        }))
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.rate);
+        self.updated = now;
+    }
+}
+
+// 单个方向上生效的令牌桶：隧道自身的，以及所属用户共享的
+struct Direction {
+    buckets: Vec<Arc<Mutex<Bucket>>>,
+    sleep: Option<Pin<Box<Sleep>>>,
+}
+
+impl Direction {
+    // 从所有令牌桶中取出最多 want 个字节，令牌不足时等待
+    fn poll_take(&mut self, cx: &mut TaskContext<'_>, want: usize) -> Poll<usize> {
+        if self.buckets.is_empty() || want == 0 {
+            return Poll::Ready(want);
+        }
+        loop {
+            if let Some(sleep) = &mut self.sleep {
+                ready!(sleep.as_mut().poll(cx));
+                self.sleep = None;
+            }
+
+            // 按隧道、用户的固定顺序加锁，避免死锁
+            let now = Instant::now();
+            let mut buckets: Vec<_> = self.buckets.iter().map(|b| b.lock().unwrap()).collect();
+            let mut allowed = want;
+            let mut wait = Duration::ZERO;
+            for bucket in buckets.iter_mut() {
+                bucket.refill(now);
+                if bucket.tokens < 1.0 {
+                    wait = wait.max(Duration::from_secs_f64((1.0 - bucket.tokens) / bucket.rate));
+                } else {
+                    allowed = allowed.min(bucket.tokens as usize);
+                }
+            }
+            if wait.is_zero() {
+                for bucket in buckets.iter_mut() {
+                    bucket.tokens -= allowed as f64;
+                }
+                return Poll::Ready(allowed);
+            }
+            drop(buckets);
+            self.sleep = Some(Box::pin(tokio::time::sleep(wait)));
+        }
+    }
+
+    // 归还未使用的令牌
+    fn refund(&self, unused: usize) {
+        for bucket in &self.buckets {
+            let mut bucket = bucket.lock().unwrap();
+            bucket.tokens = (bucket.tokens + unused as f64).min(bucket.rate);
+        }
+    }
+}
+
+// 限制上游连接的读写速度，按隧道和用户分别计算
+pub struct Throttled<S> {
+    inner: S,
+    read: Direction,
+    write: Direction,
+}
+
+impl<S> Throttled<S> {
+    pub fn new(inner: S, ctx: &Context) -> Self {
+        let limit = ctx.limit();
+        let rate = |bandwidth: &Option<String>| {
+            bandwidth
+                .as_deref()
+                .and_then(|bandwidth| parse_size(bandwidth).ok())
+                .filter(|rate| *rate > 0)
+        };
+
+        let mut read = Vec::new();
+        let mut write = Vec::new();
+        if let Some(rate) = rate(&limit.bandwidth) {
+            read.push(Bucket::new(rate));
+            write.push(Bucket::new(rate));
+        }
+        if let (Some(user), Some(rate)) = (&ctx.user, rate(&limit.user_bandwidth)) {
+            let (user_read, user_write) = LIMITER.bandwidth(user, rate);
+            read.push(user_read);
+            write.push(user_write);
+        }
+        Throttled {
+            inner,
+            read: Direction {
+                buckets: read,
+                sleep: None,
+            },
+            write: Direction {
+                buckets: write,
+                sleep: None,
+            },
+        }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Throttled<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        let remaining = buf.remaining();
+        let allowed = ready!(this.read.poll_take(cx, remaining));
+
+        // 令牌不足时读取到较小的临时缓冲区
+        let result = if allowed < remaining {
+            let mut temp = vec![0u8; allowed];
+            let mut temp_buf = ReadBuf::new(&mut temp);
+            let result = Pin::new(&mut this.inner).poll_read(cx, &mut temp_buf);
+            buf.put_slice(temp_buf.filled());
+            result
+        } else {
+            Pin::new(&mut this.inner).poll_read(cx, buf)
+        };
+        let read = remaining - buf.remaining();
+        this.read.refund(allowed - read);
+        result
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Throttled<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        let allowed = ready!(this.write.poll_take(cx, buf.len()));
+        let result = Pin::new(&mut this.inner).poll_write(cx, &buf[..allowed]);
+        let written = match &result {
+            Poll::Ready(Ok(written)) => *written,
+            _ => 0,
+        };
+        this.write.refund(allowed - written);
+        result
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
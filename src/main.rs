@@ -1,79 +1,107 @@
 use anyhow::Result;
+use clap::Parser;
 use tokio::signal;
 use tracing::{error, info};
 use x_proxy_pool::{
-    common::{self, config::CONFIG},
-    protocol::{http::http_proxy, model::Protocol, socks5::socks5_proxy},
+    access, api,
+    cli::{Cli, Command, command},
+    common::{
+        self,
+        config::{self, CONFIG},
+    },
+    geoip,
     proxy::{self, model::PROXY_POOL},
-    util::check_proxy_protocol,
+    route, server, traffic,
 };
 
 #[tokio::main]
 async fn main() -> Result<()> {
+    let cli = Cli::parse();
+    if let Some(path) = &cli.config {
+        config::set_path(path);
+    }
+    config::set_overrides(cli.overrides);
+
+    // 输出配置时不校验，便于排查合并后的配置；只有启动服务时要求代理文件已经存在
+    let command = cli.command.unwrap_or(Command::Serve);
+    if !matches!(command, Command::Config | Command::Validate) {
+        config::load(matches!(command, Command::Serve))?;
+    }
+
+    match command {
+        Command::Serve => serve().await,
+        Command::Check { file, format } => command::check(&file, format).await,
+        Command::Import { file, format } => command::import(&file, format),
+        Command::Export { output, format } => command::export(output.as_deref(), format),
+        Command::Validate => command::validate(),
+        Command::Config => command::config(),
+    }
+}
+
+async fn serve() -> Result<()> {
     // 初始化通用模块
     common::init()?;
+    geoip::init().await?;
     proxy::init().await?;
+    route::init().await?;
+    traffic::init().await?;
+    access::init()?;
 
     // 启动服务
     let server_handle = tokio::spawn(async move {
-        let local_address = format!("{}:{}", CONFIG.server.host, CONFIG.server.port);
-        if let Err(e) = run(&local_address).await {
+        if let Err(e) = server::run().await {
             error!("服务器错误: {}", e);
         }
     });
 
-    tokio::select! {
-        _ = signal::ctrl_c() => {
-            info!("接收到 Ctrl+C 信号，正在关闭服务...");
+    // 定期保存流量统计
+    tokio::spawn(async move {
+        if let Err(e) = traffic::run().await {
+            error!("流量统计错误: {}", e);
         }
+    });
+
+    // 启动 API 服务
+    if CONFIG.api.enable {
+        tokio::spawn(async move {
+            if let Err(e) = api::run().await {
+                error!("API 服务错误: {}", e);
+            }
+        });
     }
 
-    // 中止服务器任务
+    // 等待 Ctrl+C 或 SIGTERM
+    shutdown_signal().await;
+    info!("接收到关闭信号，停止接受新连接...");
+
+    // 等待已有连接结束
+    server::shutdown().await;
     server_handle.abort();
 
+    // 保存代理池和流量统计，写完剩余的访问日志
+    if let Err(e) = PROXY_POOL.save().await {
+        error!("保存代理池失败: {}", e);
+    }
+    if let Err(e) = traffic::model::TRAFFIC.save().await {
+        error!("保存流量统计失败: {}", e);
+    }
+    access::model::ACCESS_LOG.close().await;
+
+    info!("服务已关闭");
     Ok(())
 }
 
-pub async fn run(address: &str) -> Result<()> {
-    let listener = tokio::net::TcpListener::bind(address).await?;
-    info!("服务启动在: {}", address);
-
-    loop {
-        match listener.accept().await {
-            Ok((source_stream, source_address)) => {
-                info!("接受到新连接: {}", source_address);
-                tokio::spawn(async move {
-                    if let Err(e) = handle_connection(source_stream).await {
-                        error!("连接处理出错: {}", e);
-                    }
-                });
-            }
-            Err(e) => {
-                error!("接受连接失败: {}", e);
-            }
-        }
+#[cfg(unix)]
+async fn shutdown_signal() {
+    let mut terminate =
+        signal::unix::signal(signal::unix::SignalKind::terminate()).expect("注册 SIGTERM 信号失败");
+    tokio::select! {
+        _ = signal::ctrl_c() => {}
+        _ = terminate.recv() => {}
     }
 }
 
-pub async fn handle_connection(mut stream: tokio::net::TcpStream) -> Result<()> {
-    let source_connect_protocol = check_proxy_protocol(&mut stream).await?;
-    let (mut reader, mut writer) = stream.split();
-    info!("代理协议为: {:?}", source_connect_protocol);
-    match source_connect_protocol {
-        Protocol::Http => {
-            // 处理 HTTP 请求
-            let proxy = PROXY_POOL.get(Protocol::Http).await?;
-            if let Err(e) = http_proxy(&mut reader, &mut writer, &proxy).await {
-                error!("处理 HTTP 请求出错: {}", e);
-            }
-        }
-        Protocol::Socks5 => {
-            // 处理 SOCKS5 请求
-            let proxy = PROXY_POOL.get(Protocol::Socks5).await?;
-            if let Err(e) = socks5_proxy(&mut reader, &mut writer, &proxy).await {
-                error!("处理 SOCKS5 请求出错: {}", e);
-            }
-        }
-    }
-    Ok(())
+#[cfg(not(unix))]
+async fn shutdown_signal() {
+    let _ = signal::ctrl_c().await;
 }
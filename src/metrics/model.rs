@@ -0,0 +1,216 @@
+use std::{sync::Arc, time::Duration};
+
+use anyhow::Result;
+use once_cell::sync::Lazy;
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry,
+    TextEncoder,
+};
+
+use crate::{
+    common::config::CONFIG,
+    proxy::model::{PROXY_POOL, Proxy},
+};
+
+pub struct Metrics {
+    registry: Registry,
+    // 入站连接，按入站协议 (http / socks5 / transparent) 区分
+    accepted: IntCounterVec,
+    rejected: IntCounterVec,
+    active: IntGaugeVec,
+    // 隧道选择上游，按上游和标签区分
+    upstream_selections: IntCounterVec,
+    tag_selections: IntCounterVec,
+    // 连接上游失败
+    upstream_failures: IntCounterVec,
+    tag_failures: IntCounterVec,
+    // 连接上游并完成握手的耗时
+    upstream_latency: HistogramVec,
+    tag_latency: HistogramVec,
+    // 上游检测结果
+    health_checks: IntCounterVec,
+    // 代理池中的上游数量，按协议区分
+    pool_size: IntGaugeVec,
+    // 隧道转发的字节数，按方向区分
+    relay_bytes: IntCounterVec,
+    // 作为 tag 维度的上游标签
+    tags: Vec<String>,
+}
+
+// 活跃连接计数，连接结束时减一
+pub struct Active(IntGauge);
+
+impl Drop for Active {
+    fn drop(&mut self) {
+        self.0.dec();
+    }
+}
+
+impl Metrics {
+    pub fn new(tags: Vec<String>) -> Result<Self> {
+        let registry = Registry::new_custom(Some("x_proxy_pool".to_string()), None)?;
+        let counter = |name: &str, help: &str, labels: &[&str]| -> Result<IntCounterVec> {
+            let counter = IntCounterVec::new(Opts::new(name, help), labels)?;
+            registry.register(Box::new(counter.clone()))?;
+            Ok(counter)
+        };
+        let gauge = |name: &str, help: &str, labels: &[&str]| -> Result<IntGaugeVec> {
+            let gauge = IntGaugeVec::new(Opts::new(name, help), labels)?;
+            registry.register(Box::new(gauge.clone()))?;
+            Ok(gauge)
+        };
+        let histogram = |name: &str, help: &str, labels: &[&str]| -> Result<HistogramVec> {
+            let histogram = HistogramVec::new(HistogramOpts::new(name, help), labels)?;
+            registry.register(Box::new(histogram.clone()))?;
+            Ok(histogram)
+        };
+
+        Ok(Metrics {
+            accepted: counter(
+                "connections_accepted_total",
+                "Accepted inbound connections",
+                &["protocol"],
+            )?,
+            rejected: counter(
+                "connections_rejected_total",
+                "Rejected inbound connections",
+                &["protocol", "reason"],
+            )?,
+            active: gauge(
+                "connections_active",
+                "Active inbound connections",
+                &["protocol"],
+            )?,
+            upstream_selections: counter(
+                "upstream_selections_total",
+                "Upstream selections per upstream",
+                &["upstream"],
+            )?,
+            tag_selections: counter(
+                "upstream_tag_selections_total",
+                "Upstream selections per tag",
+                &["tag"],
+            )?,
+            upstream_failures: counter(
+                "upstream_failures_total",
+                "Upstream connect failures per upstream",
+                &["upstream"],
+            )?,
+            tag_failures: counter(
+                "upstream_tag_failures_total",
+                "Upstream connect failures per tag",
+                &["tag"],
+            )?,
+            upstream_latency: histogram(
+                "upstream_connect_seconds",
+                "Upstream connect and handshake latency per upstream",
+                &["upstream"],
+            )?,
+            tag_latency: histogram(
+                "upstream_tag_connect_seconds",
+                "Upstream connect and handshake latency per tag",
+                &["tag"],
+            )?,
+            health_checks: counter(
+                "health_checks_total",
+                "Upstream health check outcomes",
+                &["result"],
+            )?,
+            pool_size: gauge("pool_size", "Upstreams in the pool", &["protocol"])?,
+            relay_bytes: counter(
+                "relay_bytes_total",
+                "Bytes relayed through tunnels",
+                &["direction"],
+            )?,
+            registry,
+            tags,
+        })
+    }
+
+    // 接受入站连接，返回的计数在连接结束时释放
+    pub fn accept(&self, protocol: &str) -> Active {
+        self.accepted.with_label_values(&[protocol]).inc();
+        let active = self.active.with_label_values(&[protocol]);
+        active.inc();
+        Active(active)
+    }
+
+    pub fn reject(&self, protocol: &str, reason: &str) {
+        self.rejected.with_label_values(&[protocol, reason]).inc();
+    }
+
+    pub fn select(&self, proxy: &Proxy) {
+        self.upstream_selections
+            .with_label_values(&[&proxy.show()])
+            .inc();
+        for tag in self.tags(proxy) {
+            self.tag_selections.with_label_values(&[&tag]).inc();
+        }
+    }
+
+    // 记录一次上游连接的结果
+    pub fn connect(&self, proxy: &Proxy, elapsed: Duration, success: bool) {
+        let upstream = proxy.show();
+        if success {
+            let seconds = elapsed.as_secs_f64();
+            self.upstream_latency
+                .with_label_values(&[&upstream])
+                .observe(seconds);
+            for tag in self.tags(proxy) {
+                self.tag_latency.with_label_values(&[&tag]).observe(seconds);
+            }
+        } else {
+            self.upstream_failures.with_label_values(&[&upstream]).inc();
+            for tag in self.tags(proxy) {
+                self.tag_failures.with_label_values(&[&tag]).inc();
+            }
+        }
+    }
+
+    pub fn health_check(&self, success: bool) {
+        let result = if success { "success" } else { "failure" };
+        self.health_checks.with_label_values(&[result]).inc();
+    }
+
+    pub fn relay(&self, upload: u64, download: u64) {
+        if upload > 0 {
+            self.relay_bytes
+                .with_label_values(&["upload"])
+                .inc_by(upload);
+        }
+        if download > 0 {
+            self.relay_bytes
+                .with_label_values(&["download"])
+                .inc_by(download);
+        }
+    }
+
+    // 导出 Prometheus 文本格式的全部指标
+    pub async fn gather(&self) -> Result<String> {
+        let http = PROXY_POOL.http_proxy_list.read().await.len();
+        let socks5 = PROXY_POOL.socks5_proxy_list.read().await.len();
+        self.pool_size.with_label_values(&["http"]).set(http as i64);
+        self.pool_size
+            .with_label_values(&["socks5"])
+            .set(socks5 as i64);
+
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+        Ok(String::from_utf8(buffer)?)
+    }
+
+    // 上游在 metric_tags 中列出的标签，格式为 key=value，上游没有的标签跳过
+    fn tags(&self, proxy: &Proxy) -> Vec<String> {
+        self.tags
+            .iter()
+            .filter_map(|key| proxy.label(key).map(|value| format!("{}={}", key, value)))
+            .collect()
+    }
+}
+
+pub fn init() -> Result<Arc<Metrics>> {
+    Ok(Arc::new(Metrics::new(CONFIG.api.metric_tags.clone())?))
+}
+
+// 全局访问指标
+pub static METRICS: Lazy<Arc<Metrics>> = Lazy::new(|| init().unwrap());
@@ -0,0 +1,279 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use base64::{Engine, engine::general_purpose::STANDARD};
+use tracing::warn;
+
+use crate::{
+    common::config::CONFIG,
+    metrics::model::METRICS,
+    protocol::model::Context,
+    proxy::{selector::Selector, session::Session},
+};
+
+// 用户名中携带的路由参数
+#[derive(Debug, Clone, Default)]
+pub struct UsernameParams {
+    pub session: Option<Session>,
+    pub selector: Selector,
+}
+
+// 校验用户名和密码
+pub fn verify(username: &str, password: &str) -> bool {
+    CONFIG
+        .user
+        .iter()
+        .any(|user| user.username == username && user.password == password)
+}
+
+// 认证并记录用户，监听器开启 username_params 时先从用户名中拆出路由参数
+pub fn login(ctx: &mut Context, username: &str, password: &str) -> bool {
+    let (username, params) = if ctx.listener.username_params {
+        match parse_username(username) {
+            Ok(result) => result,
+            Err(_) => return false,
+        }
+    } else {
+        (username.to_string(), UsernameParams::default())
+    };
+    if !verify(&username, password) {
+        METRICS.reject(ctx.inbound, "auth");
+        return false;
+    }
+    ctx.user = Some(username);
+    ctx.params = params;
+    // 用户名参数与监听器或用户已限定的标签冲突时认证失败
+    if let Err(e) = ctx.selector() {
+        warn!("用户名参数无效: {} - {}", ctx.client_address, e);
+        METRICS.reject(ctx.inbound, "auth");
+        ctx.user = None;
+        ctx.params = UsernameParams::default();
+        return false;
+    }
+    true
+}
+
+// 解析 alice-session-abc123-country-us-rotate-10m 形式的用户名
+// 用户名之后为 key-value 对: session 为会话标识，rotate 为会话有效期或 now (立即更换上游)，其余为标签
+pub fn parse_username(str: &str) -> Result<(String, UsernameParams)> {
+    let usernames: Vec<&str> = CONFIG
+        .user
+        .iter()
+        .map(|user| user.username.as_str())
+        .collect();
+    split_username(
+        str,
+        &usernames,
+        Duration::from_secs(CONFIG.proxy.session_ttl),
+        Duration::from_secs(CONFIG.proxy.max_session_ttl),
+    )
+}
+
+// 从已配置的用户名中找出 str 的用户名部分，并解析其后的参数
+// rotate 指定的有效期不超过 max_ttl
+fn split_username(
+    str: &str,
+    usernames: &[&str],
+    mut ttl: Duration,
+    max_ttl: Duration,
+) -> Result<(String, UsernameParams)> {
+    let mut params = UsernameParams::default();
+    if usernames.contains(&str) {
+        return Ok((str.to_string(), params));
+    }
+
+    // 用户名本身可能包含 '-'，取最长的已配置用户名前缀
+    let username = usernames
+        .iter()
+        .copied()
+        .filter(|username| {
+            str.strip_prefix(username)
+                .is_some_and(|rest| rest.starts_with('-'))
+        })
+        .max_by_key(|username| username.len())
+        .ok_or_else(|| anyhow::anyhow!("未知用户: {}", str))?;
+
+    let parts: Vec<&str> = str[username.len() + 1..].split('-').collect();
+    if !parts.len().is_multiple_of(2) {
+        return Err(anyhow::anyhow!("无效的用户名参数: {}", str));
+    }
+
+    let mut session = None;
+    let mut rotate = false;
+    for pair in parts.chunks(2) {
+        let (key, value) = (pair[0], pair[1]);
+        if key.is_empty() || value.is_empty() {
+            return Err(anyhow::anyhow!("无效的用户名参数: {}", str));
+        }
+        match key {
+            "session" => session = Some(value),
+            "rotate" if value == "now" => rotate = true,
+            "rotate" => ttl = parse_duration(value)?.min(max_ttl),
+            _ => params.selector = params.selector.with(key, value),
+        }
+    }
+
+    params.session = session.map(|session| Session {
+        id: format!("{}:{}", username, session),
+        ttl,
+        rotate,
+    });
+    Ok((username.to_string(), params))
+}
+
+// 解析 30s / 10m / 2h 形式的时长，不带单位时为秒
+fn parse_duration(str: &str) -> Result<Duration> {
+    let (number, unit) = match str.char_indices().last() {
+        Some((index, unit)) if unit.is_ascii_alphabetic() => (&str[..index], unit),
+        _ => (str, 's'),
+    };
+    let number: u64 = number
+        .parse()
+        .map_err(|_| anyhow::anyhow!("无效的时长: {}", str))?;
+    let unit = match unit {
+        's' => 1,
+        'm' => 60,
+        'h' => 3600,
+        _ => return Err(anyhow::anyhow!("无效的时长: {}", str)),
+    };
+    let seconds = number
+        .checked_mul(unit)
+        .ok_or_else(|| anyhow::anyhow!("时长超出范围: {}", str))?;
+    Ok(Duration::from_secs(seconds))
+}
+
+// 解析 Proxy-Authorization: Basic xxx
+pub fn parse_basic(value: &str) -> Option<(String, String)> {
+    let (scheme, credentials) = value.trim().split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("basic") {
+        return None;
+    }
+    let decoded = STANDARD.decode(credentials.trim()).ok()?;
+    let decoded = String::from_utf8(decoded).ok()?;
+    let (username, password) = decoded.split_once(':')?;
+    Some((username.to_string(), password.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const USERS: [&str; 3] = ["alice", "bob", "bob-team"];
+    const TTL: Duration = Duration::from_secs(600);
+    const MAX_TTL: Duration = Duration::from_secs(86400);
+
+    fn split(str: &str) -> Result<(String, UsernameParams)> {
+        split_username(str, &USERS, TTL, MAX_TTL)
+    }
+
+    #[test]
+    fn plain_username() {
+        let (username, params) = split("alice").unwrap();
+        assert_eq!(username, "alice");
+        assert!(params.session.is_none());
+        assert!(params.selector.is_empty());
+    }
+
+    #[test]
+    fn params() {
+        let (username, params) = split("alice-session-abc123-country-us-rotate-10m").unwrap();
+        assert_eq!(username, "alice");
+        let session = params.session.unwrap();
+        assert_eq!(session.id, "alice:abc123");
+        assert_eq!(session.ttl, Duration::from_secs(600));
+        assert!(!session.rotate);
+        assert_eq!(params.selector, Selector::from("country=us").unwrap());
+    }
+
+    #[test]
+    fn rotate_now() {
+        let (_, params) = split("alice-session-s1-rotate-now").unwrap();
+        let session = params.session.unwrap();
+        assert!(session.rotate);
+        assert_eq!(session.ttl, TTL);
+    }
+
+    #[test]
+    fn rotate_without_session() {
+        let (_, params) = split("alice-rotate-30s-type-residential").unwrap();
+        assert!(params.session.is_none());
+        assert_eq!(params.selector, Selector::from("type=residential").unwrap());
+    }
+
+    #[test]
+    fn longest_prefix() {
+        let (username, params) = split("bob-team-country-de").unwrap();
+        assert_eq!(username, "bob-team");
+        assert_eq!(params.selector, Selector::from("country=de").unwrap());
+
+        let (username, params) = split("bob-country-de").unwrap();
+        assert_eq!(username, "bob");
+        assert_eq!(params.selector, Selector::from("country=de").unwrap());
+
+        // 与已配置的用户名完全相同时不解析参数
+        assert_eq!(split("bob-team").unwrap().0, "bob-team");
+    }
+
+    #[test]
+    fn invalid() {
+        for str in [
+            "mallory",
+            "mallory-country-us",
+            "alicex-country-us",
+            "alice-country",
+            "alice-country-us-session",
+            "alice-country--x-y",
+            "alice-",
+            "alice-session-",
+            "alice-rotate-10d",
+            "alice-rotate-m",
+        ] {
+            assert!(split(str).is_err(), "{}", str);
+        }
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("45").unwrap(), Duration::from_secs(45));
+        assert_eq!(parse_duration("30s").unwrap(), Duration::from_secs(30));
+        assert_eq!(parse_duration("10m").unwrap(), Duration::from_secs(600));
+        assert_eq!(parse_duration("2h").unwrap(), Duration::from_secs(7200));
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("1.5h").is_err());
+        assert!(parse_duration("99999999999999999h").is_err());
+        assert!(parse_duration("18446744073709551615m").is_err());
+        assert!(parse_duration("99999999999999999999s").is_err());
+    }
+
+    #[test]
+    fn ttl_is_capped() {
+        let (_, params) = split("alice-session-s1-rotate-48h").unwrap();
+        assert_eq!(params.session.unwrap().ttl, MAX_TTL);
+        assert!(split("alice-session-s1-rotate-99999999999999999h").is_err());
+    }
+
+    #[test]
+    fn params_only_narrow() {
+        let listener = Selector::from("group=residential,country=de").unwrap();
+        let (_, params) = split("alice-group-datacenter").unwrap();
+        assert!(listener.clone().narrow(&params.selector).is_err());
+
+        let (_, params) = split("alice-country-DE-city-berlin").unwrap();
+        let narrowed = listener.narrow(&params.selector).unwrap();
+        assert_eq!(
+            narrowed,
+            Selector::from("group=residential,country=de,city=berlin").unwrap()
+        );
+    }
+
+    #[test]
+    fn basic() {
+        let encoded = STANDARD.encode("alice-country-us:pa:ss");
+        assert_eq!(
+            parse_basic(&format!("Basic {}", encoded)),
+            Some(("alice-country-us".to_string(), "pa:ss".to_string()))
+        );
+        assert_eq!(parse_basic(&format!("Bearer {}", encoded)), None);
+        assert_eq!(parse_basic("Basic !!!"), None);
+    }
+}
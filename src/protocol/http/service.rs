@@ -1,25 +1,42 @@
+use std::{
+    io,
+    pin::Pin,
+    task::{Context as TaskContext, Poll, ready},
+};
+
 use anyhow::Result;
-use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
 use tracing::{error, info, trace};
 
-use crate::proxy::model::Proxy;
+use crate::{
+    common::config::ListenerAuth,
+    protocol::{
+        auth,
+        model::{Address, Context, Protocol},
+        upstream::{self, Outbound},
+    },
+    util::{Cause, relay},
+};
 
-pub async fn http_proxy<R, W>(reader: &mut R, writer: &mut W, proxy: &Proxy) -> Result<()>
+pub async fn http_proxy<R, W>(reader: &mut R, writer: &mut W, ctx: &mut Context) -> Result<()>
 where
     R: AsyncRead + Unpin,
     W: AsyncWrite + Unpin,
 {
     trace!("启动 HTTP 代理");
 
-    // 读取客户端请求
+    // 读取客户端请求，直到请求头完整
     let mut request = Vec::new();
     let mut buf = [0u8; 1024];
-    loop {
+    while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+        if request.len() > MAX_HEAD_SIZE {
+            return Err(anyhow::anyhow!("请求头过长: {}", ctx.client_address));
+        }
         let n = reader.read(&mut buf).await?;
-        request.extend_from_slice(&buf[..n]);
-        if n < 1024 {
-            break;
+        if n == 0 {
+            return Err(anyhow::anyhow!("请求头不完整: {}", ctx.client_address));
         }
+        request.extend_from_slice(&buf[..n]);
     }
     trace!("请求头: {}", String::from_utf8_lossy(&request));
 
@@ -27,9 +44,12 @@ where
     let mut headers = vec![httparse::EMPTY_HEADER; 16];
     let mut req = httparse::Request::new(&mut headers);
 
-    let _res = loop {
+    let head_len = loop {
         match req.parse(&request) {
-            Ok(status) => break status,
+            Ok(httparse::Status::Complete(len)) => break len,
+            Ok(httparse::Status::Partial) => {
+                return Err(anyhow::anyhow!("请求头不完整: {}", ctx.client_address));
+            }
             Err(httparse::Error::TooManyHeaders) => {
                 headers.extend_from_slice(&[httparse::EMPTY_HEADER; 16]);
                 req = httparse::Request::new(&mut headers);
@@ -39,56 +59,224 @@ where
     };
 
     // 获取请求方法和路径
-    let method = req.method.ok_or_else(|| anyhow::anyhow!("缺失请求方法"))?;
-    let path = req.path.ok_or_else(|| anyhow::anyhow!("缺失请求路径"))?;
+    let method = req
+        .method
+        .ok_or_else(|| anyhow::anyhow!("缺失请求方法"))?
+        .to_string();
+    let path = req
+        .path
+        .ok_or_else(|| anyhow::anyhow!("缺失请求路径"))?
+        .to_string();
+    let header = |name: &str| {
+        req.headers
+            .iter()
+            .find(|header| header.name.eq_ignore_ascii_case(name))
+            .map(|header| String::from_utf8_lossy(header.value).to_string())
+    };
+    let authorization = header("Proxy-Authorization");
+    let host = header("Host");
+    let content_length = header("Content-Length");
+    let chunked = header("Transfer-Encoding").is_some();
+
+    // 认证
+    if ctx.listener.auth == ListenerAuth::Password {
+        match authorization.as_deref().and_then(auth::parse_basic) {
+            Some((username, password)) if auth::login(ctx, &username, &password) => {}
+            _ => {
+                let response = b"HTTP/1.1 407 Proxy Authentication Required\r\nProxy-Authenticate: Basic realm=\"x-proxy-pool\"\r\nContent-Length: 0\r\n\r\n";
+                writer.write_all(response).await?;
+                return Err(anyhow::anyhow!("HTTP 认证失败: {}", ctx.client_address));
+            }
+        }
+    }
+
+    // 解析目标地址，CONNECT 使用请求路径，普通请求使用 URI 或 Host 头
+    let target = if method == "CONNECT" {
+        Address::parse(&path, 443)?
+    } else {
+        let authority = match path.split_once("://") {
+            Some((_, rest)) => rest.split('/').next().unwrap_or_default().to_string(),
+            None => host.ok_or_else(|| anyhow::anyhow!("缺失 Host 请求头"))?,
+        };
+        Address::parse(&authority, 80)?
+    };
+    ctx.target = Some(target.clone());
+
+    // 普通请求每个连接只转发一个请求，需要知道请求体的长度
+    let (forward, remaining) = if method == "CONNECT" {
+        (request[head_len..].to_vec(), 0)
+    } else {
+        if chunked {
+            let response =
+                b"HTTP/1.1 411 Length Required\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+            writer.write_all(response).await?;
+            return Err(anyhow::anyhow!("不支持分块传输的请求体: {}", target));
+        }
+        let length: u64 = match content_length
+            .as_deref()
+            .map(|length| length.trim().parse())
+        {
+            None => 0,
+            Some(Ok(length)) => length,
+            Some(Err(_)) => {
+                let response =
+                    b"HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+                writer.write_all(response).await?;
+                return Err(anyhow::anyhow!("无效的 Content-Length: {}", target));
+            }
+        };
+        // 已读取的部分请求体随请求头一起转发，超出请求体的数据丢弃
+        let body = &request[head_len..];
+        let included = body.len().min(length as usize);
+        let mut forward = rewrite_head(&request[..head_len]);
+        forward.extend_from_slice(&body[..included]);
+        (forward, length - included as u64)
+    };
+
+    // 超过连接限制
+    if !ctx.acquire() {
+        let response = b"HTTP/1.1 429 Too Many Requests\r\nContent-Length: 0\r\n\r\n";
+        writer.write_all(response).await?;
+        return Err(anyhow::anyhow!("超过连接限制: {}", ctx.client_address));
+    }
+
+    // 根据路由规则选择出站方式
+    let outbound = match ctx.outbound(Some(Protocol::Http)).await {
+        Ok(Outbound::Block) => {
+            let response = b"HTTP/1.1 403 Forbidden\r\nContent-Length: 0\r\n\r\n";
+            writer.write_all(response).await?;
+            return Err(anyhow::anyhow!("目标被路由规则拒绝: {}", target));
+        }
+        Ok(outbound) => outbound,
+        Err(e) => {
+            let response = b"HTTP/1.1 502 Bad Gateway\r\nContent-Length: 0\r\n\r\n";
+            writer.write_all(response).await?;
+            return Err(e);
+        }
+    };
 
     // 连接目标服务器
-    let mut proxy_stream = match tokio::net::TcpStream::connect(proxy.address()).await {
+    let connect = async {
+        match (method == "CONNECT", &outbound) {
+            // 普通请求直接交给上游 HTTP 代理处理
+            (false, Outbound::Proxy(proxy)) if matches!(proxy.scheme, Protocol::Http) => {
+                upstream::open(proxy, ctx).await
+            }
+            _ => upstream::dial(&outbound, &target, ctx).await,
+        }
+    };
+    let mut proxy_stream = match connect.await {
         Ok(stream) => {
-            info!("成功连接到目标服务器: {}", proxy.show());
-            stream
+            info!("成功连接到目标服务器: {} -> {}", outbound.show(), target);
+            ctx.establish(stream, &outbound)
         }
         Err(e) => {
             error!("无法连接到目标服务器: {}", e);
+            ctx.reason = Some(e.to_string());
             let response = b"HTTP/1.1 502 Bad Gateway\r\nContent-Length: 0\r\n\r\n";
             writer.write_all(response).await?;
             return Ok(());
         }
     };
 
-    if method == "CONNECT" {
+    // 双向转发数据，每个方向独立关闭
+    let relayed = if method == "CONNECT" {
         trace!("处理 CONNECT 请求: {}", path);
-        let addr = path.split(':').collect::<Vec<_>>();
-        if addr.len() != 2 {
-            return Err(anyhow::anyhow!("无效的 CONNECT 目标地址: {}", path));
-        }
-        // 发送 CONNECT 请求到代理服务器
-        let connect_request = format!("CONNECT {} HTTP/1.1\r\nHost: {}\r\n\r\n", path, path);
-        proxy_stream.write_all(connect_request.as_bytes()).await?;
+        writer
+            .write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")
+            .await?;
+        // 客户端在收到响应前发送的数据
+        proxy_stream.write_all(&forward).await?;
+        relay(reader, writer, proxy_stream).await
     } else {
         // 转发客户端请求到目标服务器
-        proxy_stream.write_all(&request).await?;
+        proxy_stream.write_all(&forward).await?;
         info!("已转发客户端请求");
+        let mut reader = SingleRequest { reader, remaining };
+        relay(&mut reader, writer, proxy_stream).await
+    };
+    ctx.reason = Some(relayed.cause.to_string());
+    if let Cause::Closed = relayed.cause {
+        info!("隧道结束: {} - {}", target, relayed);
+    } else {
+        error!("隧道异常结束: {} - {}", target, relayed);
     }
 
-    // 转发目标服务器响应到客户端
-    let (mut proxy_reader, mut proxy_writer) = proxy_stream.into_split();
-    let client_to_proxy = tokio::io::copy(reader, &mut proxy_writer);
-    let proxy_to_client = tokio::io::copy(&mut proxy_reader, writer);
+    info!("结束 HTTP 代理");
+    Ok(())
+}
 
-    tokio::select! {
-        res = client_to_proxy => {
-            if let Err(e) = res {
-                error!("客户端到服务器传输错误: {}", e);
-            }
-        },
-        res = proxy_to_client => {
-            if let Err(e) = res {
-                error!("服务器到客户端传输错误: {}", e);
+// 转发给上游前去除的请求头: 客户端的认证信息和连接管理字段
+const HOP_BY_HOP: [&str; 4] = [
+    "proxy-authorization",
+    "proxy-connection",
+    "connection",
+    "keep-alive",
+];
+
+// 请求头的最大长度
+const MAX_HEAD_SIZE: usize = 64 * 1024;
+
+// 改写转发的请求头: 去除 HOP_BY_HOP 中的字段，并要求上游处理完这个请求后关闭连接
+fn rewrite_head(head: &[u8]) -> Vec<u8> {
+    let mut rewritten = Vec::with_capacity(head.len() + 19);
+    for (i, line) in head.split(|&b| b == b'\n').enumerate() {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        if line.is_empty() {
+            continue;
+        }
+        if i > 0 {
+            let name = line.split(|&b| b == b':').next().unwrap_or_default();
+            let name = String::from_utf8_lossy(name).trim().to_ascii_lowercase();
+            if HOP_BY_HOP.contains(&name.as_str()) {
+                continue;
             }
         }
+        rewritten.extend_from_slice(line);
+        rewritten.extend_from_slice(b"\r\n");
     }
+    rewritten.extend_from_slice(b"Connection: close\r\n\r\n");
+    rewritten
+}
 
-    info!("结束 HTTP 代理");
-    Ok(())
+// 普通请求的客户端读取端: 只转发当前请求剩余的请求体，之后客户端发送的数据全部丢弃
+// 同一连接上的后续请求不会经过认证和路由，不能转发给上游
+struct SingleRequest<'a, R> {
+    reader: &'a mut R,
+    // 尚未转发的请求体字节数
+    remaining: u64,
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for SingleRequest<'_, R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        if buf.remaining() == 0 {
+            return Poll::Ready(Ok(()));
+        }
+        let mut chunk = [0u8; 8192];
+        loop {
+            let limit = if self.remaining > 0 {
+                chunk
+                    .len()
+                    .min(buf.remaining())
+                    .min(self.remaining as usize)
+            } else {
+                chunk.len()
+            };
+            let mut read = ReadBuf::new(&mut chunk[..limit]);
+            ready!(Pin::new(&mut *self.reader).poll_read(cx, &mut read))?;
+            let n = read.filled().len();
+            if n == 0 {
+                return Poll::Ready(Ok(()));
+            }
+            if self.remaining > 0 {
+                buf.put_slice(&chunk[..n]);
+                self.remaining -= n as u64;
+                return Poll::Ready(Ok(()));
+            }
+        }
+    }
 }
@@ -1,3 +1,6 @@
+pub mod auth;
 pub mod http;
 pub mod model;
 pub mod socks5;
+pub mod transparent;
+pub mod upstream;
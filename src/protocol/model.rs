@@ -1,5 +1,300 @@
+use std::{fmt, net::SocketAddr, sync::Arc};
+
+use anyhow::Result;
+use tracing::{trace, warn};
+
+use crate::{
+    common::config::{CONFIG, Limit, Listener, User},
+    limit::{
+        model::{LIMITER, Permit},
+        throttle::Throttled,
+    },
+    metrics::model::METRICS,
+    protocol::{auth::UsernameParams, upstream::Outbound},
+    proxy::{
+        limit::Lease,
+        model::{PROXY_POOL, Proxy},
+        selector::Selector,
+    },
+    route::model::{ROUTER, Route},
+    traffic::{meter::Metered, model::TRAFFIC},
+    util::{Activity, Tracked},
+};
+
 #[derive(Debug, Clone)]
 pub enum Protocol {
     Http,
     Socks5,
 }
+
+// 目标地址，host 可以是域名或 IP
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Address {
+    pub host: String,
+    pub port: u16,
+}
+
+impl Address {
+    pub fn new(host: String, port: u16) -> Self {
+        Address { host, port }
+    }
+
+    // 解析 host:port 或 [ipv6]:port，未写端口时使用 default_port
+    pub fn parse(str: &str, default_port: u16) -> Result<Self> {
+        let str = str.trim();
+        let (host, port) = if let Some(rest) = str.strip_prefix('[') {
+            let (host, rest) = rest
+                .split_once(']')
+                .ok_or_else(|| anyhow::anyhow!("无效的地址: {}", str))?;
+            if !rest.is_empty() && !rest.starts_with(':') {
+                return Err(anyhow::anyhow!("无效的地址: {}", str));
+            }
+            (host, rest.strip_prefix(':'))
+        } else {
+            match str.rsplit_once(':') {
+                Some((host, port)) if !host.contains(':') => (host, Some(port)),
+                _ => (str, None),
+            }
+        };
+        if host.is_empty() {
+            return Err(anyhow::anyhow!("无效的地址: {}", str));
+        }
+        let port = match port {
+            Some(port) => port
+                .parse()
+                .map_err(|_| anyhow::anyhow!("无效的端口: {}", str))?,
+            None => default_port,
+        };
+        Ok(Address::new(host.to_string(), port))
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.host.contains(':') {
+            write!(f, "[{}]:{}", self.host, self.port)
+        } else {
+            write!(f, "{}:{}", self.host, self.port)
+        }
+    }
+}
+
+// 单个入站连接的上下文
+#[derive(Debug)]
+pub struct Context {
+    pub listener: Arc<Listener>,
+    pub client_address: SocketAddr,
+    pub local_address: SocketAddr,
+    pub user: Option<String>,
+    // 客户端请求的目标地址，透明代理模式下为原始目标
+    pub target: Option<Address>,
+    // 端口映射模式下固定使用的上游
+    pub fixed_upstream: Option<Proxy>,
+    // 用户名中携带的路由参数
+    pub params: UsernameParams,
+    // 连接限制的许可，连接结束时释放
+    pub permit: Option<Permit>,
+    // 占用的上游连接，连接结束时释放
+    pub lease: Option<Lease>,
+    // 连接的活动状态，用于超时
+    pub activity: Activity,
+    // 识别出的入站协议，用于指标和访问日志
+    pub inbound: &'static str,
+    // 选择的出站方式和选择次数，用于访问日志
+    pub upstream: Option<String>,
+    pub attempts: usize,
+    // 连接结束的原因，用于访问日志
+    pub reason: Option<String>,
+}
+
+impl Context {
+    pub fn new(
+        listener: Arc<Listener>,
+        client_address: SocketAddr,
+        local_address: SocketAddr,
+    ) -> Self {
+        Context {
+            listener,
+            client_address,
+            local_address,
+            user: None,
+            target: None,
+            fixed_upstream: None,
+            params: UsernameParams::default(),
+            permit: None,
+            lease: None,
+            activity: Activity::new(),
+            inbound: "unknown",
+            upstream: None,
+            attempts: 0,
+            reason: None,
+        }
+    }
+
+    // 与目标建立隧道: 包装上游连接，记录活动时间、限速并统计流量
+    pub fn establish<S>(&self, stream: S, outbound: &Outbound) -> Metered<Throttled<Tracked<S>>> {
+        self.activity.establish();
+        let stream = Tracked::new(stream, &self.activity);
+        Metered::new(Throttled::new(stream, self), self, outbound)
+    }
+
+    // 认证用户的配置
+    fn user_config(&self) -> Option<&'static User> {
+        let username = self.user.as_deref()?;
+        CONFIG.user.iter().find(|user| user.username == username)
+    }
+
+    // 当前连接的限制，认证用户的配置覆盖 [limit] 中的同名项
+    pub fn limit(&self) -> Limit {
+        match self.user_config() {
+            Some(user) => CONFIG.limit.merge(&user.limit),
+            None => CONFIG.limit.clone(),
+        }
+    }
+
+    // 检查连接限制，认证用户按用户计算，否则按客户端 IP 计算
+    // 认证用户的流量配额已用完时同样拒绝
+    pub fn acquire(&mut self) -> bool {
+        let key = match self.user_config() {
+            Some(user) if TRAFFIC.exceeded(&format!("user:{}", user.username)) => {
+                warn!("用户 {} 的流量配额已用完", user.username);
+                METRICS.reject(self.inbound, "quota");
+                return false;
+            }
+            Some(user) => format!("user:{}", user.username),
+            None => self.client_address.ip().to_string(),
+        };
+        let limit = self.limit();
+        if limit.is_empty() {
+            return true;
+        }
+        self.permit = LIMITER.acquire(&key, &limit);
+        if self.permit.is_none() {
+            METRICS.reject(self.inbound, "limit");
+        }
+        self.permit.is_some()
+    }
+
+    // 当前连接的上游选择器: 监听器的分组和选择器，叠加认证用户的选择器
+    // 用户名参数中的标签只能缩小范围，不能覆盖已限定的标签
+    pub fn selector(&self) -> Result<Selector> {
+        let mut selector = Selector::for_listener(&self.listener)?;
+        if let Some(str) = self.user_config().and_then(|user| user.selector.as_deref()) {
+            selector = selector.merge(&Selector::from(str)?);
+        }
+        selector.narrow(&self.params.selector)
+    }
+
+    // 按选择器选取上游，未指定协议时不限协议，端口映射模式下使用固定上游，同样占用连接数并检查配额
+    pub async fn upstream(&mut self, scheme: Option<Protocol>) -> Result<Proxy> {
+        if let Some(proxy) = self.fixed_upstream.clone() {
+            // 同一连接内已占用该上游时不重复占用
+            if self
+                .lease
+                .as_ref()
+                .is_none_or(|lease| lease.proxy.show() != proxy.show())
+            {
+                match PROXY_POOL.hold(&proxy).await {
+                    Ok(lease) => self.lease = Some(lease),
+                    Err(e) => {
+                        METRICS.reject(self.inbound, "limit");
+                        return Err(e);
+                    }
+                }
+            }
+            return Ok(proxy);
+        }
+        let selector = self.selector()?;
+        self.lease(scheme, &selector).await
+    }
+
+    // 从代理池中占用一个上游连接，直到连接结束
+    async fn lease(&mut self, scheme: Option<Protocol>, selector: &Selector) -> Result<Proxy> {
+        let lease = PROXY_POOL
+            .get(scheme, selector, self.params.session.as_ref())
+            .await?;
+        let proxy = lease.proxy.clone();
+        self.lease = Some(lease);
+        Ok(proxy)
+    }
+
+    // 根据路由规则决定目标的出站方式
+    pub async fn outbound(&mut self, scheme: Option<Protocol>) -> Result<Outbound> {
+        let target = self
+            .target
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("缺失目标地址"))?;
+        let route = ROUTER.route(target, self.user.as_deref()).await;
+        trace!("路由结果: {} -> {:?}", target, route);
+        self.attempts += 1;
+        let proxy = match route {
+            Route::Direct => {
+                self.upstream = Some(Outbound::Direct.show());
+                return Ok(Outbound::Direct);
+            }
+            Route::Block => {
+                self.upstream = Some(Outbound::Block.show());
+                return Ok(Outbound::Block);
+            }
+            Route::Pool => self.upstream(scheme).await?,
+            Route::Group(group) => {
+                let selector = self.selector()?.with("group", &group);
+                self.lease(scheme, &selector).await?
+            }
+        };
+        METRICS.select(&proxy);
+        self.upstream = Some(proxy.show());
+        Ok(Outbound::Proxy(proxy))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(str: &str) -> Option<(String, u16)> {
+        Address::parse(str, 80)
+            .ok()
+            .map(|address| (address.host, address.port))
+    }
+
+    #[test]
+    fn address_parse() {
+        let address = |host: &str, port| Some((host.to_string(), port));
+        assert_eq!(parse("example.com:443"), address("example.com", 443));
+        assert_eq!(parse(" example.com "), address("example.com", 80));
+        assert_eq!(parse("1.2.3.4:8080"), address("1.2.3.4", 8080));
+        assert_eq!(parse("[2001:db8::1]:443"), address("2001:db8::1", 443));
+        assert_eq!(parse("[2001:db8::1]"), address("2001:db8::1", 80));
+        // 未加括号的 IPv6 地址不拆分端口
+        assert_eq!(parse("2001:db8::1"), address("2001:db8::1", 80));
+    }
+
+    #[test]
+    fn address_parse_invalid() {
+        for str in [
+            "",
+            ":443",
+            "[]:443",
+            "[2001:db8::1",
+            "[2001:db8::1]443",
+            "example.com:",
+            "example.com:http",
+            "example.com:65536",
+        ] {
+            assert_eq!(parse(str), None, "{}", str);
+        }
+    }
+
+    #[test]
+    fn address_display() {
+        assert_eq!(
+            Address::parse("[::1]:80", 0).unwrap().to_string(),
+            "[::1]:80"
+        );
+        assert_eq!(
+            Address::parse("example.com:80", 0).unwrap().to_string(),
+            "example.com:80"
+        );
+    }
+}
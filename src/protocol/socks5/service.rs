@@ -1,10 +1,20 @@
+use std::net::{Ipv4Addr, Ipv6Addr};
+
 use anyhow::Result;
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
-use tracing::{error, trace};
-
-use crate::proxy::model::Proxy;
-
-pub async fn socks5_proxy<R, W>(reader: &mut R, writer: &mut W, proxy: &Proxy) -> Result<()>
+use tracing::{error, info, trace};
+
+use crate::{
+    common::config::ListenerAuth,
+    protocol::{
+        auth,
+        model::{Address, Context, Protocol},
+        upstream::{self, Outbound},
+    },
+    util::{Cause, relay},
+};
+
+pub async fn socks5_proxy<R, W>(reader: &mut R, writer: &mut W, ctx: &mut Context) -> Result<()>
 where
     R: AsyncRead + Unpin,
     W: AsyncWrite + Unpin,
@@ -21,10 +31,35 @@ where
     let mut methods = vec![0u8; nmethods as usize];
     reader.read_exact(&mut methods).await?;
 
-    // 不需要认证，回复使用无认证方法
-    writer.write_all(&[0x05, 0x00]).await?;
+    // 根据监听器的认证方式选择方法: 0x00 无认证, 0x02 用户名密码
+    let need_auth = ctx.listener.auth == ListenerAuth::Password;
+    let method = if need_auth { 0x02 } else { 0x00 };
+    if !methods.contains(&method) {
+        writer.write_all(&[0x05, 0xFF]).await?;
+        return Err(anyhow::anyhow!("客户端不支持所需的认证方式"));
+    }
+    writer.write_all(&[0x05, method]).await?;
     writer.flush().await?;
 
+    // 用户名密码认证 (RFC 1929)
+    if need_auth {
+        let _version = reader.read_u8().await?;
+        let username_len = reader.read_u8().await? as usize;
+        let mut username = vec![0u8; username_len];
+        reader.read_exact(&mut username).await?;
+        let password_len = reader.read_u8().await? as usize;
+        let mut password = vec![0u8; password_len];
+        reader.read_exact(&mut password).await?;
+
+        let username = String::from_utf8_lossy(&username).to_string();
+        let password = String::from_utf8_lossy(&password).to_string();
+        if !auth::login(ctx, &username, &password) {
+            writer.write_all(&[0x01, 0x01]).await?;
+            return Err(anyhow::anyhow!("SOCKS5 认证失败: {}", username));
+        }
+        writer.write_all(&[0x01, 0x00]).await?;
+    }
+
     trace!("结束 SOCKS5 握手处理");
 
     // 读取SOCKS5请求
@@ -37,12 +72,12 @@ where
 
     // 读取目标地址
     let addr_type = buf[3];
-    let target_addr = match addr_type {
+    let host = match addr_type {
         0x01 => {
             // IPv4
             let mut addr = [0u8; 4];
             reader.read_exact(&mut addr).await?;
-            format!("{}.{}.{}.{}", addr[0], addr[1], addr[2], addr[3])
+            Ipv4Addr::from(addr).to_string()
         }
         0x03 => {
             // 域名
@@ -55,37 +90,44 @@ where
             // IPv6
             let mut addr = [0u8; 16];
             reader.read_exact(&mut addr).await?;
-            format!(
-                "{:02x}{:02x}:{:02x}{:02x}:{:02x}{:02x}:{:02x}{:02x}:{:02x}{:02x}:{:02x}{:02x}:{:02x}{:02x}:{:02x}{:02x}",
-                addr[0],
-                addr[1],
-                addr[2],
-                addr[3],
-                addr[4],
-                addr[5],
-                addr[6],
-                addr[7],
-                addr[8],
-                addr[9],
-                addr[10],
-                addr[11],
-                addr[12],
-                addr[13],
-                addr[14],
-                addr[15]
-            )
+            Ipv6Addr::from(addr).to_string()
         }
         _ => return Err(anyhow::anyhow!("不支持的地址类型")),
     };
 
     // 读取端口
     let port = reader.read_u16().await?;
+    let target = Address::new(host, port);
+    ctx.target = Some(target.clone());
 
-    // 获取代理
-    let mut upstream = match tokio::net::TcpStream::connect(proxy.address()).await {
-        Ok(stream) => stream,
+    // 超过连接限制
+    if !ctx.acquire() {
+        // 0x02: 规则不允许的连接
+        let response = [0x05, 0x02, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
+        writer.write_all(&response).await?;
+        return Err(anyhow::anyhow!("超过连接限制: {}", ctx.client_address));
+    }
+
+    // 根据路由规则选择出站方式
+    let outbound = match ctx.outbound(Some(Protocol::Socks5)).await {
+        Ok(Outbound::Block) => {
+            // 0x02: 规则不允许的连接
+            let response = [0x05, 0x02, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
+            writer.write_all(&response).await?;
+            return Err(anyhow::anyhow!("目标被路由规则拒绝: {}", target));
+        }
+        Ok(outbound) => outbound,
         Err(e) => {
-            error!("代理连接失败: {} - {}", proxy.address(), e);
+            let response = [0x05, 0x01, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
+            writer.write_all(&response).await?;
+            return Err(e);
+        }
+    };
+    let upstream = match upstream::dial(&outbound, &target, ctx).await {
+        Ok(stream) => ctx.establish(stream, &outbound),
+        Err(e) => {
+            error!("代理连接失败: {} -> {} - {}", outbound.show(), target, e);
+            ctx.reason = Some(e.to_string());
             // 发送失败响应
             let response = [0x05, 0x04, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
             writer.write_all(&response).await?;
@@ -93,112 +135,17 @@ where
         }
     };
 
-    // 与上游SOCKS5服务器进行握手
-    upstream.write_all(&[0x05, 0x01, 0x00]).await?;
-    let mut response = [0u8; 2];
-    upstream.read_exact(&mut response).await?;
-
-    if response[0] != 0x05 || response[1] != 0x00 {
-        eprintln!("上游代理握手失败");
-        return Ok(());
-    }
-
-    // 发送连接请求到上游代理
-    let mut request = Vec::new();
-    request.extend_from_slice(&[0x05, 0x01, 0x00]); // VER, CMD, RSV
-
-    match addr_type {
-        0x01 => {
-            // IPv4
-            request.push(0x01);
-            for octet in target_addr.split('.') {
-                request.push(octet.parse::<u8>()?);
-            }
-        }
-        0x03 => {
-            // Domain
-            request.push(0x03);
-            request.push(target_addr.len() as u8);
-            request.extend_from_slice(target_addr.as_bytes());
-        }
-        0x04 => {
-            // IPv6
-            request.push(0x04);
-
-            // 将 IPv6 地址字符串转换为 16 字节的数组
-            let addr = target_addr
-                .split(':')
-                .map(|part| u16::from_str_radix(part, 16).unwrap_or(0))
-                .collect::<Vec<_>>();
-
-            // 将每组 2 字节的十六进制数转换为大端字节序并添加到请求中
-            for &value in &addr {
-                request.extend_from_slice(&value.to_be_bytes());
-            }
-        }
-        _ => unreachable!(),
-    }
-
-    // 添加端口
-    request.extend_from_slice(&port.to_be_bytes());
-
-    // 发送请求到上游代理
-    upstream.write_all(&request).await?;
-
-    // 读取上游代理响应
-    let mut response = [0u8; 4];
-    upstream.read_exact(&mut response).await?;
-
-    if response[1] != 0x00 {
-        error!("上游代理连接目标失败");
-        let response = [0x05, 0x04, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
-        writer.write_all(&response).await?;
-        return Ok(());
-    }
-
-    // 跳过绑定地址和端口
-    match response[3] {
-        0x01 => {
-            // IPv4
-            let mut addr = [0u8; 4];
-            upstream.read_exact(&mut addr).await?;
-        }
-        0x03 => {
-            // Domain
-            let len = upstream.read_u8().await?;
-            let mut domain = vec![0u8; len as usize];
-            upstream.read_exact(&mut domain).await?;
-        }
-        0x04 => {
-            // IPv6
-            let mut addr = [0u8; 16];
-            upstream.read_exact(&mut addr).await?;
-        }
-        _ => return Err(anyhow::anyhow!("上游代理返回了不支持的地址类型")),
-    }
-    let mut port = [0u8; 2];
-    upstream.read_exact(&mut port).await?;
-
     // 发送成功响应给客户端
     let response = [0x05, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
     writer.write_all(&response).await?;
 
-    // 双向转发数据
-    let (mut upstream_reader, mut upstream_writer) = upstream.into_split();
-    let client_to_proxy = tokio::io::copy(reader, &mut upstream_writer);
-    let proxy_to_client = tokio::io::copy(&mut upstream_reader, writer);
-
-    tokio::select! {
-        res = client_to_proxy => {
-            if let Err(e) = res {
-                error!("客户端到代理传输错误: {}", e);
-            }
-        },
-        res = proxy_to_client => {
-            if let Err(e) = res {
-                error!("代理到客户端传输错误: {}", e);
-            }
-        }
+    // 双向转发数据，每个方向独立关闭
+    let relayed = relay(reader, writer, upstream).await;
+    ctx.reason = Some(relayed.cause.to_string());
+    if let Cause::Closed = relayed.cause {
+        info!("隧道结束: {} - {}", target, relayed);
+    } else {
+        error!("隧道异常结束: {} - {}", target, relayed);
     }
     Ok(())
 }
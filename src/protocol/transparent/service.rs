@@ -0,0 +1,45 @@
+use anyhow::Result;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tracing::{error, info, trace};
+
+use crate::{
+    protocol::{model::Context, upstream},
+    util::{Cause, relay},
+};
+
+pub async fn transparent_proxy<R, W>(
+    reader: &mut R,
+    writer: &mut W,
+    ctx: &mut Context,
+) -> Result<()>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let target = ctx
+        .target
+        .clone()
+        .ok_or_else(|| anyhow::anyhow!("缺失原始目标地址"))?;
+    trace!("启动透明代理: {}", target);
+
+    // 超过连接限制时直接断开
+    if !ctx.acquire() {
+        return Err(anyhow::anyhow!("超过连接限制: {}", ctx.client_address));
+    }
+
+    // 根据路由规则选择出站方式，并建立到原始目标的隧道，socks5 和 http 上游都可以使用
+    let outbound = ctx.outbound(None).await?;
+    let upstream = upstream::dial(&outbound, &target, ctx).await?;
+    let upstream = ctx.establish(upstream, &outbound);
+    info!("成功连接到目标服务器: {} -> {}", outbound.show(), target);
+
+    // 双向转发数据，每个方向独立关闭
+    let relayed = relay(reader, writer, upstream).await;
+    ctx.reason = Some(relayed.cause.to_string());
+    if let Cause::Closed = relayed.cause {
+        info!("隧道结束: {} - {}", target, relayed);
+    } else {
+        error!("隧道异常结束: {} - {}", target, relayed);
+    }
+    Ok(())
+}
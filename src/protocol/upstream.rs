@@ -0,0 +1,158 @@
+use std::{net::IpAddr, time::Instant};
+
+use anyhow::Result;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+use crate::{
+    metrics::model::METRICS,
+    protocol::model::{Address, Context, Protocol},
+    proxy::model::Proxy,
+    util::encode_proxy_header,
+};
+
+// 路由后的出站方式
+#[derive(Debug, Clone)]
+pub enum Outbound {
+    Direct,
+    Block,
+    Proxy(Proxy),
+}
+
+impl Outbound {
+    pub fn show(&self) -> String {
+        match self {
+            Outbound::Direct => "direct".to_string(),
+            Outbound::Block => "block".to_string(),
+            Outbound::Proxy(proxy) => proxy.show(),
+        }
+    }
+}
+
+// 按出站方式连接目标地址
+pub async fn dial(outbound: &Outbound, target: &Address, ctx: &Context) -> Result<TcpStream> {
+    match outbound {
+        Outbound::Direct => Ok(TcpStream::connect(target.to_string()).await?),
+        Outbound::Block => Err(anyhow::anyhow!("目标被路由规则拒绝: {}", target)),
+        Outbound::Proxy(proxy) => connect(proxy, target, ctx).await,
+    }
+}
+
+// 连接上游代理，上游支持时先发送 PROXY 协议头
+pub async fn open(proxy: &Proxy, ctx: &Context) -> Result<TcpStream> {
+    observe(proxy, open_stream(proxy, ctx)).await
+}
+
+async fn open_stream(proxy: &Proxy, ctx: &Context) -> Result<TcpStream> {
+    let mut stream = TcpStream::connect(proxy.address()).await?;
+    if let Some(version) = proxy.proxy_protocol {
+        let header = encode_proxy_header(version, ctx.client_address, ctx.local_address);
+        stream.write_all(&header).await?;
+    }
+    Ok(stream)
+}
+
+// 通过上游代理连接目标地址，返回已建立隧道的连接
+pub async fn connect(proxy: &Proxy, target: &Address, ctx: &Context) -> Result<TcpStream> {
+    observe(proxy, async {
+        let mut stream = open_stream(proxy, ctx).await?;
+        match proxy.scheme {
+            Protocol::Socks5 => socks5_connect(&mut stream, target).await?,
+            Protocol::Http => http_connect(&mut stream, target).await?,
+        }
+        Ok(stream)
+    })
+    .await
+}
+
+// 记录连接上游的结果和耗时
+async fn observe(
+    proxy: &Proxy,
+    connecting: impl Future<Output = Result<TcpStream>>,
+) -> Result<TcpStream> {
+    let start = Instant::now();
+    let result = connecting.await;
+    METRICS.connect(proxy, start.elapsed(), result.is_ok());
+    result
+}
+
+async fn socks5_connect(stream: &mut TcpStream, target: &Address) -> Result<()> {
+    // 与上游SOCKS5服务器进行握手
+    stream.write_all(&[0x05, 0x01, 0x00]).await?;
+    let mut response = [0u8; 2];
+    stream.read_exact(&mut response).await?;
+
+    if response[0] != 0x05 || response[1] != 0x00 {
+        return Err(anyhow::anyhow!("上游代理握手失败"));
+    }
+
+    // 发送连接请求到上游代理
+    let mut request = Vec::new();
+    request.extend_from_slice(&[0x05, 0x01, 0x00]); // VER, CMD, RSV
+    match target.host.parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) => {
+            request.push(0x01);
+            request.extend_from_slice(&ip.octets());
+        }
+        Ok(IpAddr::V6(ip)) => {
+            request.push(0x04);
+            request.extend_from_slice(&ip.octets());
+        }
+        Err(_) => {
+            if target.host.len() > 255 {
+                return Err(anyhow::anyhow!("域名过长: {}", target.host));
+            }
+            request.push(0x03);
+            request.push(target.host.len() as u8);
+            request.extend_from_slice(target.host.as_bytes());
+        }
+    }
+    request.extend_from_slice(&target.port.to_be_bytes());
+    stream.write_all(&request).await?;
+
+    // 读取上游代理响应
+    let mut response = [0u8; 4];
+    stream.read_exact(&mut response).await?;
+
+    if response[1] != 0x00 {
+        return Err(anyhow::anyhow!(
+            "上游代理连接目标失败: {:#04x}",
+            response[1]
+        ));
+    }
+
+    // 跳过绑定地址和端口
+    let skip = match response[3] {
+        0x01 => 4,
+        0x03 => stream.read_u8().await? as usize,
+        0x04 => 16,
+        _ => return Err(anyhow::anyhow!("上游代理返回了不支持的地址类型")),
+    };
+    let mut bound = vec![0u8; skip + 2];
+    stream.read_exact(&mut bound).await?;
+    Ok(())
+}
+
+async fn http_connect(stream: &mut TcpStream, target: &Address) -> Result<()> {
+    let connect_request = format!("CONNECT {} HTTP/1.1\r\nHost: {}\r\n\r\n", target, target);
+    stream.write_all(connect_request.as_bytes()).await?;
+
+    // 逐字节读取响应头，避免读走隧道中的数据
+    let mut response = Vec::new();
+    while !response.ends_with(b"\r\n\r\n") {
+        if response.len() > 8192 {
+            return Err(anyhow::anyhow!("上游代理响应头过长"));
+        }
+        response.push(stream.read_u8().await?);
+    }
+
+    let mut headers = [httparse::EMPTY_HEADER; 32];
+    let mut res = httparse::Response::new(&mut headers);
+    res.parse(&response)?;
+    match res.code {
+        Some(code) if (200..300).contains(&code) => Ok(()),
+        code => Err(anyhow::anyhow!("上游代理 CONNECT 失败: {:?}", code)),
+    }
+}
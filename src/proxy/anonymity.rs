@@ -0,0 +1,169 @@
+use std::{net::IpAddr, time::Duration};
+
+use anyhow::Result;
+use tokio::{sync::OnceCell, time::timeout};
+
+// 上游的匿名级别，按隐藏程度从低到高排序
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Anonymity {
+    // 目标能看到客户端的真实 IP
+    Transparent,
+    // 隐藏了真实 IP，但请求头暴露了使用代理
+    Anonymous,
+    // 目标看不出使用了代理
+    Elite,
+}
+
+// 代理可能添加的请求头，出现任一个即说明使用了代理
+const PROXY_HEADERS: [&str; 6] = [
+    "via",
+    "x-forwarded-for",
+    "forwarded",
+    "x-real-ip",
+    "client-ip",
+    "proxy-connection",
+];
+
+// 不经过代理访问判断页面得到的本机出口 IP，只获取一次
+static LOCAL_IP: OnceCell<Option<IpAddr>> = OnceCell::const_new();
+
+impl Anonymity {
+    pub fn from(str: &str) -> Result<Self> {
+        match str {
+            "transparent" => Ok(Anonymity::Transparent),
+            "anonymous" => Ok(Anonymity::Anonymous),
+            "elite" => Ok(Anonymity::Elite),
+            _ => Err(anyhow::anyhow!("不支持的匿名级别: {}", str)),
+        }
+    }
+
+    pub fn show(&self) -> &'static str {
+        match self {
+            Anonymity::Transparent => "transparent",
+            Anonymity::Anonymous => "anonymous",
+            Anonymity::Elite => "elite",
+        }
+    }
+}
+
+// 通过上游访问判断页面，根据页面回显的来源 IP 和请求头判断匿名级别
+pub async fn detect(client: &reqwest::Client, url: &str, wait: Duration) -> Result<Anonymity> {
+    let local = LOCAL_IP
+        .get_or_init(|| async {
+            let body = fetch(
+                &reqwest::Client::builder().no_proxy().build().ok()?,
+                url,
+                wait,
+            )
+            .await
+            .ok()?;
+            origin(&body)
+        })
+        .await;
+    let body = fetch(client, url, wait).await?;
+    Ok(classify(&body, *local))
+}
+
+async fn fetch(client: &reqwest::Client, url: &str, wait: Duration) -> Result<String> {
+    let res = timeout(wait, client.get(url).send()).await??;
+    Ok(timeout(wait, res.text()).await??)
+}
+
+// 判断页面回显的来源 IP，支持 {"origin": "..."} 格式的 JSON 或只包含 IP 的文本
+fn origin(body: &str) -> Option<IpAddr> {
+    let origin = match serde_json::from_str::<serde_json::Value>(body) {
+        Ok(json) => json["origin"].as_str()?.to_string(),
+        Err(_) => body.to_string(),
+    };
+    origin.split(',').next()?.trim().parse().ok()
+}
+
+// 页面中出现本机 IP 为透明代理，出现代理请求头为普通匿名，否则为高匿
+// 请求头支持 {"headers": {...}} 格式的 JSON 或每行一个 "名称: 值" 的文本
+fn classify(body: &str, local: Option<IpAddr>) -> Anonymity {
+    let leaked = local.is_some_and(|local| {
+        body.split(|c: char| !(c.is_ascii_hexdigit() || c == '.' || c == ':'))
+            .any(|token| token.trim_matches(':').parse() == Ok(local))
+    });
+    if leaked {
+        return Anonymity::Transparent;
+    }
+
+    let headers: Vec<String> = match serde_json::from_str::<serde_json::Value>(body) {
+        Ok(json) => json["headers"]
+            .as_object()
+            .map(|headers| headers.keys().map(|key| key.to_ascii_lowercase()).collect())
+            .unwrap_or_default(),
+        Err(_) => body
+            .lines()
+            .filter_map(|line| line.split_once(':'))
+            .map(|(name, _)| name.trim().to_ascii_lowercase())
+            .collect(),
+    };
+    if headers
+        .iter()
+        .any(|name| PROXY_HEADERS.contains(&name.as_str()))
+    {
+        Anonymity::Anonymous
+    } else {
+        Anonymity::Elite
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOCAL: &str = "203.0.113.7";
+
+    fn local() -> Option<IpAddr> {
+        LOCAL.parse().ok()
+    }
+
+    #[test]
+    fn classifies_json() {
+        let elite =
+            r#"{"headers": {"Host": "example.com", "Accept": "*/*"}, "origin": "198.51.100.1"}"#;
+        assert_eq!(classify(elite, local()), Anonymity::Elite);
+
+        let anonymous =
+            r#"{"headers": {"Host": "example.com", "Via": "1.1 squid"}, "origin": "198.51.100.1"}"#;
+        assert_eq!(classify(anonymous, local()), Anonymity::Anonymous);
+
+        let transparent = r#"{"headers": {"X-Forwarded-For": "203.0.113.7"}, "origin": "203.0.113.7, 198.51.100.1"}"#;
+        assert_eq!(classify(transparent, local()), Anonymity::Transparent);
+    }
+
+    #[test]
+    fn classifies_text() {
+        assert_eq!(
+            classify("Host: example.com\nX-Real-IP: 198.51.100.1\n", local()),
+            Anonymity::Anonymous
+        );
+        assert_eq!(
+            classify("Host: example.com\nX-Client: 203.0.113.7\n", local()),
+            Anonymity::Transparent
+        );
+        // 本机 IP 只作为其他 IP 的一部分出现时不算泄露
+        assert_eq!(
+            classify("Host: example.com\nX-Client: 203.0.113.70\n", local()),
+            Anonymity::Elite
+        );
+    }
+
+    #[test]
+    fn parses_origin() {
+        assert_eq!(origin(r#"{"origin": "203.0.113.7"}"#), local());
+        assert_eq!(origin(r#"{"origin": "203.0.113.7, 10.0.0.1"}"#), local());
+        assert_eq!(origin("203.0.113.7\n"), local());
+        assert_eq!(origin("<html>"), None);
+    }
+
+    #[test]
+    fn levels_are_ordered() {
+        assert!(Anonymity::Elite > Anonymity::Anonymous);
+        assert!(Anonymity::Anonymous > Anonymity::Transparent);
+        assert_eq!(Anonymity::from("elite").unwrap(), Anonymity::Elite);
+        assert!(Anonymity::from("high").is_err());
+    }
+}
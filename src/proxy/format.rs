@@ -0,0 +1,107 @@
+use std::{collections::BTreeMap, fs};
+
+use anyhow::Result;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::util::ProxyProtocolVersion;
+
+use super::model::Proxy;
+
+// 代理列表的格式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum ListFormat {
+    // 代理文件格式: 地址 [分组] [key=value ...]
+    #[default]
+    Line,
+    // 每行一个 scheme://host:port，不包含分组和标签
+    Url,
+    // JSON 数组
+    Json,
+}
+
+// JSON 格式中的一个代理
+#[derive(Debug, Deserialize, Serialize)]
+struct Entry {
+    address: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    group: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    proxy_protocol: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    max_connections: Option<usize>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    tags: BTreeMap<String, String>,
+}
+
+// 解析代理列表，地址未写协议时同时生成 socks5 和 http
+pub fn parse(content: &str, format: ListFormat) -> Result<Vec<Proxy>> {
+    match format {
+        ListFormat::Line | ListFormat::Url => Ok(content
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .flat_map(Proxy::from_line)
+            .collect()),
+        ListFormat::Json => {
+            let entries: Vec<Entry> = serde_json::from_str(content)?;
+            let mut proxies = Vec::new();
+            for entry in entries {
+                let parsed = Proxy::from_line(&entry.address);
+                if parsed.is_empty() {
+                    return Err(anyhow::anyhow!("无效的代理地址: {}", entry.address));
+                }
+                for mut proxy in parsed {
+                    if entry.group.is_some() {
+                        proxy.group = entry.group.clone();
+                    }
+                    if let Some(version) = &entry.proxy_protocol {
+                        proxy.proxy_protocol = Some(ProxyProtocolVersion::from(version)?);
+                    }
+                    if entry.max_connections.is_some() {
+                        proxy.max_connections = entry.max_connections;
+                    }
+                    proxy.tags.extend(entry.tags.clone());
+                    proxies.push(proxy);
+                }
+            }
+            Ok(proxies)
+        }
+    }
+}
+
+pub fn render(proxies: &[Proxy], format: ListFormat) -> Result<String> {
+    match format {
+        ListFormat::Line => Ok(proxies
+            .iter()
+            .map(Proxy::line)
+            .collect::<Vec<_>>()
+            .join("\n")),
+        ListFormat::Url => Ok(proxies
+            .iter()
+            .map(Proxy::show)
+            .collect::<Vec<_>>()
+            .join("\n")),
+        ListFormat::Json => {
+            let entries: Vec<Entry> = proxies
+                .iter()
+                .map(|proxy| Entry {
+                    address: proxy.show(),
+                    group: proxy.group.clone(),
+                    proxy_protocol: proxy
+                        .proxy_protocol
+                        .map(|version| version.show().to_string()),
+                    max_connections: proxy.max_connections,
+                    tags: proxy.tags.clone(),
+                })
+                .collect();
+            Ok(serde_json::to_string_pretty(&entries)?)
+        }
+    }
+}
+
+pub fn read(path: &str, format: ListFormat) -> Result<Vec<Proxy>> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("读取代理列表 {} 失败: {}", path, e))?;
+    parse(&content, format)
+}
@@ -0,0 +1,45 @@
+use anyhow::Result;
+
+use crate::common::config::CONFIG;
+
+use super::{
+    model::{PROXY_POOL, Proxy},
+    selector::Selector,
+};
+
+// 服务商级别的并发连接数限制，匹配选择器的代理共享同一个上限
+#[derive(Debug, Clone)]
+pub struct Provider {
+    pub name: String,
+    pub selector: Selector,
+    pub max_connections: usize,
+}
+
+impl Provider {
+    pub fn load() -> Result<Vec<Provider>> {
+        CONFIG
+            .provider
+            .iter()
+            .map(|provider| {
+                Ok(Provider {
+                    name: provider.name.clone(),
+                    selector: Selector::from(&provider.selector)?,
+                    max_connections: provider.max_connections,
+                })
+            })
+            .collect()
+    }
+}
+
+// 占用上游的一个连接，释放时归还计数并唤醒排队的连接
+#[derive(Debug)]
+pub struct Lease {
+    pub proxy: Proxy,
+    pub keys: Vec<String>,
+}
+
+impl Drop for Lease {
+    fn drop(&mut self) {
+        PROXY_POOL.release(&self.keys);
+    }
+}
@@ -2,7 +2,13 @@ use anyhow::Result;
 use model::PROXY_POOL;
 use tracing::info;
 
+pub mod anonymity;
+pub mod format;
+pub mod limit;
 pub mod model;
+pub mod penalty;
+pub mod selector;
+pub mod session;
 
 pub async fn init() -> Result<()> {
     PROXY_POOL.load().await?;
@@ -1,24 +1,55 @@
 use std::{
-    collections::HashSet,
+    collections::{BTreeMap, HashMap, HashSet},
     fs::{self, File},
     io::{self, BufRead},
-    sync::Arc,
-    time::Duration,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
 };
 
 use anyhow::Result;
 use indicatif::{ProgressBar, ProgressStyle};
 use once_cell::sync::Lazy;
-use tokio::{sync::RwLock, time::timeout};
+use tokio::{
+    sync::{Notify, RwLock, watch},
+    time::{timeout, timeout_at},
+};
 use tracing::info;
 
-use crate::{common::config::CONFIG, protocol::model::Protocol};
+use crate::{
+    common::config::CONFIG,
+    geoip::model::GEOIP,
+    metrics::model::METRICS,
+    protocol::model::Protocol,
+    proxy::{
+        anonymity,
+        limit::{Lease, Provider},
+        penalty::Penalty,
+        selector::Selector,
+        session::{Session, SessionEntry},
+    },
+    traffic::model::TRAFFIC,
+    util::ProxyProtocolVersion,
+};
 
 pub struct ProxyPool {
     pub http_index: Arc<RwLock<usize>>,
     pub http_proxy_list: Arc<RwLock<Vec<Proxy>>>,
     pub socks5_index: Arc<RwLock<usize>>,
     pub socks5_proxy_list: Arc<RwLock<Vec<Proxy>>>,
+    // 不限协议时在 socks5 和 http 上游中轮询的位置
+    pub any_index: Arc<RwLock<usize>>,
+    // 代理池每次变化时递增，用于通知订阅者
+    pub changed: watch::Sender<u64>,
+    // 粘性会话绑定的上游
+    pub sessions: Arc<RwLock<HashMap<String, SessionEntry>>>,
+    // 每个上游和服务商当前的连接数
+    pub connections: Arc<Mutex<HashMap<String, usize>>>,
+    // 有连接释放时通知排队的连接
+    pub released: Arc<Notify>,
+    pub providers: Vec<Provider>,
+    // 客户端报告故障的上游
+    pub penalties: Arc<Mutex<HashMap<String, Penalty>>>,
 }
 
 #[derive(Debug, Clone)]
@@ -26,11 +57,29 @@ pub struct Proxy {
     pub scheme: Protocol,
     pub host: String,
     pub port: u16,
+    pub group: Option<String>,
+    // 向上游发送的 PROXY 协议版本
+    pub proxy_protocol: Option<ProxyProtocolVersion>,
+    // 自定义标签，例如 country=DE type=residential
+    pub tags: BTreeMap<String, String>,
+    // 检测结果和 GeoIP 补充的标签，例如 latency egress country，不写回代理文件
+    pub derived: BTreeMap<String, String>,
+    // 该上游允许的最大并发连接数
+    pub max_connections: Option<usize>,
 }
 
 impl Proxy {
     pub fn new(scheme: Protocol, host: String, port: u16) -> Self {
-        Proxy { scheme, host, port }
+        Proxy {
+            scheme,
+            host,
+            port,
+            group: None,
+            proxy_protocol: None,
+            tags: BTreeMap::new(),
+            derived: BTreeMap::new(),
+            max_connections: None,
+        }
     }
 
     pub fn from(str: &str) -> Result<Self> {
@@ -44,7 +93,7 @@ impl Proxy {
             };
             let host = parts[1].to_string().split_off(2);
             let port = parts[2].parse().unwrap_or(80);
-            return Ok(Proxy { scheme, host, port });
+            Ok(Proxy::new(scheme, host, port))
         }
         // else if length == 2 {
         //     let scheme = Protocol::Http;
@@ -53,10 +102,98 @@ impl Proxy {
         //     return Ok(Proxy { scheme, host, port });
         // }
         else {
-            return Err(anyhow::anyhow!(
+            Err(anyhow::anyhow!(
                 "期待的格式为: scheme://host:port 或 host:port"
-            ));
+            ))
+        }
+    }
+
+    // 解析代理文件中的一行，格式为: 地址 [分组] [key=value ...]
+    // 地址未写协议时，同时尝试 socks5 和 http
+    pub fn from_line(line: &str) -> Vec<Self> {
+        let mut columns = line.split_whitespace();
+        let Some(address) = columns.next() else {
+            return Vec::new();
+        };
+        let mut group = None;
+        let mut proxy_protocol = None;
+        let mut max_connections = None;
+        let mut tags = BTreeMap::new();
+        for column in columns {
+            match column.split_once('=') {
+                Some(("proxy_protocol", version)) => {
+                    proxy_protocol = ProxyProtocolVersion::from(version).ok();
+                }
+                Some(("max_connections", count)) => {
+                    max_connections = count.parse().ok();
+                }
+                Some((key, value)) => {
+                    tags.insert(key.to_string(), value.to_string());
+                }
+                None => group = Some(column.to_string()),
+            }
         }
+
+        let mut proxies = match Proxy::from(address) {
+            Ok(proxy) => vec![proxy],
+            Err(_) => ["socks5", "http"]
+                .iter()
+                .filter_map(|scheme| Proxy::from(&format!("{}://{}", scheme, address)).ok())
+                .collect(),
+        };
+        for proxy in proxies.iter_mut() {
+            proxy.group = group.clone();
+            proxy.proxy_protocol = proxy_protocol;
+            proxy.max_connections = max_connections;
+            proxy.tags = tags.clone();
+        }
+        proxies
+    }
+
+    // 写回代理文件时使用的格式
+    pub fn line(&self) -> String {
+        let mut line = self.show();
+        if let Some(group) = &self.group {
+            line.push(' ');
+            line.push_str(group);
+        }
+        if let Some(version) = &self.proxy_protocol {
+            line.push_str(" proxy_protocol=");
+            line.push_str(version.show());
+        }
+        if let Some(count) = self.max_connections {
+            line.push_str(&format!(" max_connections={}", count));
+        }
+        for (key, value) in &self.tags {
+            line.push_str(&format!(" {}={}", key, value));
+        }
+        line
+    }
+
+    // 选择器使用的标签，group 和 scheme 为内置标签
+    // 优先级: 代理自己的标签 > 检测和 GeoIP 补充的标签 > [proxy.tags] 中的默认标签
+    pub fn label(&self, key: &str) -> Option<&str> {
+        match key {
+            "group" => self.group.as_deref(),
+            "scheme" => Some(match self.scheme {
+                Protocol::Http => "http",
+                Protocol::Socks5 => "socks5",
+            }),
+            _ => self
+                .tags
+                .get(key)
+                .or_else(|| self.derived.get(key))
+                .or_else(|| CONFIG.proxy.tags.get(key))
+                .map(String::as_str),
+        }
+    }
+
+    // 生效的全部标签，包括默认标签和补充的标签，用于展示
+    pub fn labels(&self) -> BTreeMap<String, String> {
+        let mut labels = CONFIG.proxy.tags.clone();
+        labels.extend(self.derived.clone());
+        labels.extend(self.tags.clone());
+        labels
     }
 
     pub fn show(&self) -> String {
@@ -71,7 +208,8 @@ impl Proxy {
         format!("{}:{}", self.host, self.port)
     }
 
-    pub async fn test(&self) -> Result<bool> {
+    // 测试代理可用性，测试结果会记录到补充的标签中
+    pub async fn test(&mut self) -> Result<bool> {
         // Implement test logic here
         let proxy = self.show();
 
@@ -79,6 +217,7 @@ impl Proxy {
             .proxy(reqwest::Proxy::all(proxy)?)
             .build()?;
 
+        let start = Instant::now();
         let res = timeout(
             Duration::from_millis(CONFIG.proxy.timeout as u64),
             client.get("1").send(),
@@ -87,6 +226,32 @@ impl Proxy {
 
         let res = res.text().await?;
 
+        // 记录检测延迟（毫秒）
+        self.derived.insert(
+            "latency".to_string(),
+            start.elapsed().as_millis().to_string(),
+        );
+
+        // 记录出口 IP 及其地理位置
+        if let Ok(ip) = res.trim().parse::<IpAddr>() {
+            self.derived.insert("egress".to_string(), ip.to_string());
+            GEOIP.enrich(self).await;
+        }
+
+        // 记录匿名级别，判断页面访问失败时不影响检测结果
+        if let Some(url) = &CONFIG.proxy.judge_url {
+            let wait = Duration::from_millis(CONFIG.proxy.timeout as u64);
+            match anonymity::detect(&client, url, wait).await {
+                Ok(level) => {
+                    self.derived
+                        .insert("anonymity".to_string(), level.show().to_string());
+                }
+                Err(_) => {
+                    self.derived.remove("anonymity");
+                }
+            }
+        }
+
         if res != "157.245.180.34" {
             // info!("节点测试成功: {}", self.show());
             Ok(true)
@@ -96,6 +261,12 @@ impl Proxy {
     }
 }
 
+impl Default for ProxyPool {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl ProxyPool {
     pub fn new() -> Self {
         Self {
@@ -103,6 +274,13 @@ impl ProxyPool {
             http_proxy_list: Arc::new(RwLock::new(Vec::new())),
             socks5_index: Arc::new(RwLock::new(0)),
             socks5_proxy_list: Arc::new(RwLock::new(Vec::new())),
+            any_index: Arc::new(RwLock::new(0)),
+            changed: watch::channel(0).0,
+            sessions: Arc::new(RwLock::new(HashMap::new())),
+            connections: Arc::new(Mutex::new(HashMap::new())),
+            released: Arc::new(Notify::new()),
+            providers: Vec::new(),
+            penalties: Arc::new(Mutex::new(HashMap::new())),
         }
     }
 
@@ -129,34 +307,16 @@ impl ProxyPool {
         let mut http_proxy_pool = Vec::new();
         let mut socks5_proxy_pool = Vec::new();
 
-        for proxy in proxy_list {
-            let proxy = if let Ok(proxy) = Proxy::from(&proxy) {
-                proxy
-            } else {
-                if let Ok(proxy) = Proxy::from(&format!("socks5://{}", proxy)) {
-                    match proxy.scheme {
-                        Protocol::Http => http_proxy_pool.push(proxy),
-                        Protocol::Socks5 => socks5_proxy_pool.push(proxy),
-                    }
-                };
-                if let Ok(proxy) = Proxy::from(&format!("http://{}", proxy)) {
-                    match proxy.scheme {
-                        Protocol::Http => http_proxy_pool.push(proxy),
-                        Protocol::Socks5 => socks5_proxy_pool.push(proxy),
-                    }
-                };
-                continue;
-            };
-            match proxy.scheme {
-                Protocol::Http => http_proxy_pool.push(proxy),
-                Protocol::Socks5 => socks5_proxy_pool.push(proxy),
+        for line in proxy_list {
+            for mut proxy in Proxy::from_line(&line) {
+                GEOIP.enrich(&mut proxy).await;
+                match proxy.scheme {
+                    Protocol::Http => http_proxy_pool.push(proxy),
+                    Protocol::Socks5 => socks5_proxy_pool.push(proxy),
+                }
             }
         }
 
-        let mut proxy_list = Vec::new();
-        proxy_list.extend_from_slice(&http_proxy_pool);
-        proxy_list.extend_from_slice(&socks5_proxy_pool);
-
         let mut http_proxy_list = self.http_proxy_list.write().await;
         let mut socks5_proxy_list = self.socks5_proxy_list.write().await;
 
@@ -171,12 +331,15 @@ impl ProxyPool {
 
         drop(http_index);
         drop(socks5_index);
+        drop(http_proxy_list);
+        drop(socks5_proxy_list);
+        self.changed.send_modify(|version| *version += 1);
 
         let mut proxy_list = Vec::new();
         proxy_list.extend_from_slice(&http_proxy_pool);
         proxy_list.extend_from_slice(&socks5_proxy_pool);
 
-        let proxy_list: Vec<String> = proxy_list.iter().map(|p| p.show()).collect();
+        let proxy_list: Vec<String> = proxy_list.iter().map(|p| p.line()).collect();
         fs::write(&path, proxy_list.join("\n"))?;
 
         Ok(())
@@ -191,7 +354,7 @@ impl ProxyPool {
         proxy_list.extend_from_slice(&http_proxy_list);
         proxy_list.extend_from_slice(&socks5_proxy_list);
 
-        let proxy_list: Vec<String> = proxy_list.iter().map(|p| p.show()).collect();
+        let proxy_list: Vec<String> = proxy_list.iter().map(|p| p.line()).collect();
         fs::write(&path, proxy_list.join("\n"))?;
 
         Ok(())
@@ -219,11 +382,28 @@ impl ProxyPool {
 
         *http_index = 0;
         *socks5_index = 0;
+
+        drop(http_index);
+        drop(socks5_index);
+        drop(http_proxy_list);
+        drop(socks5_proxy_list);
+        self.changed.send_modify(|version| *version += 1);
         Ok(())
     }
 
+    // 获取匹配选择器的全部代理
+    pub async fn all(&self, selector: &Selector) -> Vec<Proxy> {
+        let http_proxy_list = self.http_proxy_list.read().await;
+        let socks5_proxy_list = self.socks5_proxy_list.read().await;
+        http_proxy_list
+            .iter()
+            .chain(socks5_proxy_list.iter())
+            .filter(|proxy| selector.matches(proxy))
+            .cloned()
+            .collect()
+    }
+
     pub async fn test(&self) -> Result<()> {
-        let max_test_count = CONFIG.proxy.max_test_count;
         let http_proxy_list = self.http_proxy_list.read().await;
         let socks5_proxy_list = self.socks5_proxy_list.read().await;
 
@@ -237,137 +417,426 @@ impl ProxyPool {
             return Ok(());
         }
 
-        println!(
-            "{} {} {}",
-            format!("开始代理检测..."),
-            format!(
-                "共有代理: {} http代理： {}, socks5代理: {}",
-                total,
-                http_proxy_list.len(),
-                socks5_proxy_list.len()
-            ),
-            format!("并发数: {}", max_test_count)
+        info!(
+            "开始代理检测... 共有代理: {} http代理： {}, socks5代理: {} 并发数: {}",
+            total,
+            http_proxy_list.len(),
+            socks5_proxy_list.len(),
+            CONFIG.proxy.max_test_count
         );
 
         drop(http_proxy_list);
         drop(socks5_proxy_list);
 
-        // 创建进度条
-        let pb = if true {
-            let pb = ProgressBar::new(total as u64);
-            pb.set_style(ProgressStyle::default_bar()
-                .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} ({eta})")
-                .unwrap()
-                .progress_chars("#>-"));
-            Some(Arc::new(pb))
-        } else {
-            None
-        };
+        // 保留检测成功的代理
+        let proxies: Vec<Proxy> = check(proxy_list, false)
+            .await
+            .into_iter()
+            .filter_map(|(proxy, result)| matches!(result, Ok(true)).then_some(proxy))
+            .collect();
+        info!("代理检测完成: 可用 {} / {}", proxies.len(), total);
 
-        // 创建信号量控制并发数
-        let semaphore = Arc::new(tokio::sync::Semaphore::new(max_test_count));
-        let valid_proxies = Arc::new(tokio::sync::Mutex::new(Vec::new()));
-        let mut handles = Vec::with_capacity(total);
+        self.update(proxies).await?;
+        self.save().await?;
 
-        for proxy in proxy_list {
-            let semaphore = semaphore.clone();
-            let pb = pb.clone();
-            let valid_proxies = valid_proxies.clone();
-            // let (addr, entry) = each_item(proxy);
+        Ok(())
+    }
 
-            let handle = tokio::spawn(async move {
-                // 获取信号量许可
-                let _permit = semaphore.acquire().await.unwrap();
+    fn list(&self, scheme: &Protocol) -> (&RwLock<Vec<Proxy>>, &RwLock<usize>) {
+        match scheme {
+            Protocol::Http => (&self.http_proxy_list, &self.http_index),
+            Protocol::Socks5 => (&self.socks5_proxy_list, &self.socks5_index),
+        }
+    }
 
-                // 测试代理
-                let result = proxy.test().await;
+    // 在匹配选择器的代理中轮询获取，带会话时在有效期内固定使用同一个上游
+    // 未指定协议时从 socks5 和 http 上游中一起选择
+    // 所有匹配的代理连接数都已满时，按 queue_timeout 排队等待其他连接释放
+    pub async fn get(
+        &self,
+        scheme: Option<Protocol>,
+        selector: &Selector,
+        session: Option<&Session>,
+    ) -> Result<Lease> {
+        let deadline = CONFIG
+            .proxy
+            .queue_timeout
+            .map(|ms| tokio::time::Instant::now() + Duration::from_millis(ms));
+        loop {
+            // 先注册通知再尝试获取，避免错过两者之间释放的连接
+            let released = self.released.notified();
+            tokio::pin!(released);
+            released.as_mut().enable();
+
+            if let Some(lease) = self.try_get(&scheme, selector, session).await? {
+                return Ok(lease);
+            }
+            match deadline {
+                Some(deadline) if timeout_at(deadline, released).await.is_ok() => continue,
+                _ => break,
+            }
+        }
+        Err(anyhow::anyhow!("代理连接数已满: {:?} {}", scheme, selector))
+    }
 
-                // 更新进度条
-                if let Some(pb) = &pb {
-                    pb.inc(1);
-                }
+    // 占用指定的上游，用于端口映射的固定上游，连接数已满时同样按 queue_timeout 排队等待
+    pub async fn hold(&self, proxy: &Proxy) -> Result<Lease> {
+        let deadline = CONFIG
+            .proxy
+            .queue_timeout
+            .map(|ms| tokio::time::Instant::now() + Duration::from_millis(ms));
+        loop {
+            let released = self.released.notified();
+            tokio::pin!(released);
+            released.as_mut().enable();
+
+            if let Some(lease) = self.acquire(proxy) {
+                return Ok(lease);
+            }
+            match deadline {
+                Some(deadline) if timeout_at(deadline, released).await.is_ok() => continue,
+                _ => break,
+            }
+        }
+        Err(anyhow::anyhow!(
+            "代理连接数已满或流量配额已用完: {}",
+            proxy.show()
+        ))
+    }
 
-                // 如果测试成功，添加到有效代理列表
-                if let Ok(res) = result {
-                    if res {
-                        let mut proxies = valid_proxies.lock().await;
-                        proxies.push(proxy);
-                    }
-                }
-            });
+    // 获取一个连接数未满的上游，全部已满时返回 None
+    async fn try_get(
+        &self,
+        scheme: &Option<Protocol>,
+        selector: &Selector,
+        session: Option<&Session>,
+    ) -> Result<Option<Lease>> {
+        // 与更新代理池时的加锁顺序一致，先 http 后 socks5
+        let http_proxy_list = self.http_proxy_list.read().await;
+        let socks5_proxy_list = self.socks5_proxy_list.read().await;
+        let candidates = self.candidates(&http_proxy_list, &socks5_proxy_list, scheme, selector);
+        if candidates.is_empty() {
+            return Err(anyhow::anyhow!("没有可用的代理: {:?} {}", scheme, selector));
+        }
 
-            handles.push(handle);
+        // 会话绑定的上游仍然可用且匹配选择器时继续使用
+        if let Some(session) = session.filter(|session| !session.rotate) {
+            let sessions = self.sessions.read().await;
+            if let Some(entry) = sessions.get(&session.id)
+                && entry.expires > Instant::now()
+                && let Some(proxy) = candidates
+                    .iter()
+                    .find(|proxy| proxy.show() == entry.proxy.show())
+                && let Some(lease) = self.acquire(proxy)
+            {
+                info!("会话 {} 使用: {}", session.id, proxy.show());
+                return Ok(Some(lease));
+            }
         }
 
-        // 等待所有测试完成
-        for handle in handles {
-            _ = handle.await
+        // 从上次的位置开始轮询，跳过连接数已满的上游
+        let index = match scheme {
+            Some(Protocol::Http) => &self.http_index,
+            Some(Protocol::Socks5) => &self.socks5_index,
+            None => &self.any_index,
+        };
+        let mut index = index.write().await;
+        let Some((position, lease)) = (1..=candidates.len()).find_map(|offset| {
+            let position = (*index + offset) % candidates.len();
+            self.acquire(candidates[position])
+                .map(|lease| (position, lease))
+        }) else {
+            return Ok(None);
+        };
+        *index = position;
+        drop(index);
+        let proxy = &lease.proxy;
+
+        if let Some(session) = session {
+            let now = Instant::now();
+            let mut sessions = self.sessions.write().await;
+            sessions.retain(|_, entry| entry.expires > now);
+            sessions.insert(
+                session.id.clone(),
+                SessionEntry {
+                    proxy: proxy.clone(),
+                    expires: now + session.ttl,
+                },
+            );
+            info!("会话 {} 绑定: {}", session.id, proxy.show());
+        } else {
+            info!("当前使用: {}", proxy.show());
         }
+        Ok(Some(lease))
+    }
 
-        // 结束进度条
-        if let Some(pb) = pb {
-            pb.finish_with_message(format!("测试完成"));
+    // 占用上游的一个连接，上游或其所属服务商的连接数已满、服务商流量配额已用完时返回 None
+    fn acquire(&self, proxy: &Proxy) -> Option<Lease> {
+        let mut limits = vec![(format!("proxy:{}", proxy.show()), proxy.max_connections)];
+        for provider in self.providers.iter().filter(|p| p.selector.matches(proxy)) {
+            if TRAFFIC.exceeded(&format!("provider:{}", provider.name)) {
+                return None;
+            }
+            limits.push((
+                format!("provider:{}", provider.name),
+                Some(provider.max_connections),
+            ));
         }
 
-        // 获取有效代理并排序
-        let proxies = Arc::try_unwrap(valid_proxies)
-            .expect("获取有效代理失败")
-            .into_inner();
+        let mut connections = self.connections.lock().unwrap();
+        let saturated = limits.iter().any(|(key, max)| {
+            max.is_some_and(|max| connections.get(key).copied().unwrap_or_default() >= max)
+        });
+        if saturated {
+            return None;
+        }
+        for (key, _) in &limits {
+            *connections.entry(key.clone()).or_default() += 1;
+        }
+        Some(Lease {
+            proxy: proxy.clone(),
+            keys: limits.into_iter().map(|(key, _)| key).collect(),
+        })
+    }
 
-        // 按延迟排序
-        // proxies.sort_by(|a, b| a.latency.cmp(&b.latency));
+    // 归还连接计数并唤醒排队的连接
+    pub fn release(&self, keys: &[String]) {
+        let mut connections = self.connections.lock().unwrap();
+        for key in keys {
+            if let Some(count) = connections.get_mut(key) {
+                *count -= 1;
+                if *count == 0 {
+                    connections.remove(key);
+                }
+            }
+        }
+        drop(connections);
+        self.released.notify_waiters();
+    }
 
-        self.update(proxies).await?;
-        self.save().await?;
+    // 匹配选择器且不在故障冷却期的上游，未指定协议时 socks5 在前
+    fn candidates<'a>(
+        &self,
+        http_proxy_list: &'a [Proxy],
+        socks5_proxy_list: &'a [Proxy],
+        scheme: &Option<Protocol>,
+        selector: &Selector,
+    ) -> Vec<&'a Proxy> {
+        let lists = match scheme {
+            Some(Protocol::Http) => vec![http_proxy_list],
+            Some(Protocol::Socks5) => vec![socks5_proxy_list],
+            None => vec![socks5_proxy_list, http_proxy_list],
+        };
+        lists
+            .into_iter()
+            .flatten()
+            .filter(|proxy| selector.matches(proxy) && !self.penalized(proxy))
+            .collect()
+    }
 
-        Ok(())
+    // 获取匹配选择器的上游供客户端直接连接，不占用连接数，也不影响轮询位置
+    pub async fn pick(
+        &self,
+        scheme: Option<Protocol>,
+        selector: &Selector,
+        count: usize,
+    ) -> Vec<Proxy> {
+        let http_proxy_list = self.http_proxy_list.read().await;
+        let socks5_proxy_list = self.socks5_proxy_list.read().await;
+        self.candidates(&http_proxy_list, &socks5_proxy_list, &scheme, selector)
+            .into_iter()
+            .take(count)
+            .cloned()
+            .collect()
     }
 
-    pub async fn get(&self, scheme: Protocol) -> Result<Proxy> {
-        let proxy = match scheme {
-            Protocol::Http => {
-                let http_proxy_list = self.http_proxy_list.read().await;
-                let mut http_index = self.http_index.write().await;
+    // 上游是否处于故障冷却期
+    fn penalized(&self, proxy: &Proxy) -> bool {
+        self.penalties
+            .lock()
+            .unwrap()
+            .get(&proxy.show())
+            .is_some_and(|penalty| penalty.until > Instant::now())
+    }
 
-                *http_index = (*http_index + 1) % http_proxy_list.len();
-                http_proxy_list[*http_index].clone()
-            }
-            Protocol::Socks5 => {
-                let socks5_proxy_list = self.socks5_proxy_list.read().await;
-                let mut socks5_index = self.socks5_index.write().await;
+    // 客户端报告上游故障: 冷却期内不再选择该上游，冷却期内累计 retry_count 次时移出代理池
+    // 上游不存在时返回 false
+    pub async fn report(&self, address: &str) -> Result<bool> {
+        let exists = self
+            .all(&Selector::new())
+            .await
+            .iter()
+            .any(|proxy| proxy.show() == address);
+        if !exists {
+            return Ok(false);
+        }
 
-                *socks5_index = (*socks5_index + 1) % socks5_proxy_list.len();
-                socks5_proxy_list[*socks5_index].clone()
-            }
+        let now = Instant::now();
+        let failures = {
+            let mut penalties = self.penalties.lock().unwrap();
+            penalties.retain(|_, penalty| penalty.until > now);
+            let penalty = penalties.entry(address.to_string()).or_insert(Penalty {
+                failures: 0,
+                until: now,
+            });
+            penalty.failures += 1;
+            penalty.until = now + Duration::from_secs(CONFIG.proxy.penalty);
+            penalty.failures
         };
-        info!("当前使用: {}", proxy.show());
-        Ok(proxy)
+        info!("上游被报告故障: {} 第 {} 次", address, failures);
+
+        if failures >= CONFIG.proxy.retry_count {
+            self.penalties.lock().unwrap().remove(address);
+            self.remove(address).await?;
+        } else {
+            // 解除绑定到该上游的会话，下次请求重新选择
+            self.sessions
+                .write()
+                .await
+                .retain(|_, entry| entry.proxy.show() != address);
+        }
+        Ok(true)
     }
 
-    pub async fn next(&self, scheme: Protocol) -> Result<Proxy> {
-        let proxy = match scheme {
-            Protocol::Http => {
-                let http_proxy_list = self.http_proxy_list.read().await;
-                let http_index = self.http_index.write().await;
+    // 添加上游，已存在的地址跳过，返回实际添加的数量
+    pub async fn add(&self, proxies: Vec<Proxy>) -> Result<usize> {
+        let mut added = 0;
+        for mut proxy in proxies {
+            let (proxy_list, _) = self.list(&proxy.scheme);
+            let mut proxy_list = proxy_list.write().await;
+            if proxy_list.iter().any(|p| p.show() == proxy.show()) {
+                continue;
+            }
+            GEOIP.enrich(&mut proxy).await;
+            info!("添加上游: {}", proxy.show());
+            proxy_list.push(proxy);
+            added += 1;
+        }
+        if added > 0 {
+            self.changed.send_modify(|version| *version += 1);
+            self.save().await?;
+        }
+        Ok(added)
+    }
 
-                let index = (*http_index + 1) % http_proxy_list.len();
-                http_proxy_list[index].clone()
+    // 移除上游及绑定到该上游的会话，上游不存在时返回 false
+    pub async fn remove(&self, address: &str) -> Result<bool> {
+        let mut removed = false;
+        for scheme in [Protocol::Http, Protocol::Socks5] {
+            let (proxy_list, index) = self.list(&scheme);
+            let mut proxy_list = proxy_list.write().await;
+            let before = proxy_list.len();
+            proxy_list.retain(|proxy| proxy.show() != address);
+            if proxy_list.len() != before {
+                *index.write().await = 0;
+                removed = true;
             }
-            Protocol::Socks5 => {
-                let socks5_proxy_list = self.socks5_proxy_list.read().await;
-                let socks5_index = self.socks5_index.write().await;
+        }
+        if !removed {
+            return Ok(false);
+        }
+        self.sessions
+            .write()
+            .await
+            .retain(|_, entry| entry.proxy.show() != address);
+        info!("移除上游: {}", address);
+        self.changed.send_modify(|version| *version += 1);
+        self.save().await?;
+        Ok(true)
+    }
 
-                let index = (*socks5_index + 1) % socks5_proxy_list.len();
-                socks5_proxy_list[index].clone()
+    // 强制轮换: 解除所有会话绑定，轮询位置前进一个上游
+    pub async fn rotate(&self) {
+        self.sessions.write().await.clear();
+        for scheme in [Protocol::Http, Protocol::Socks5] {
+            let (_, index) = self.list(&scheme);
+            *index.write().await += 1;
+        }
+        *self.any_index.write().await += 1;
+        info!("已强制轮换上游");
+    }
+
+    pub async fn next(&self, scheme: Protocol, selector: &Selector) -> Result<Proxy> {
+        let (proxy_list, index) = self.list(&scheme);
+        let proxy_list = proxy_list.read().await;
+        let candidates: Vec<&Proxy> = proxy_list
+            .iter()
+            .filter(|proxy| selector.matches(proxy))
+            .collect();
+        if candidates.is_empty() {
+            return Err(anyhow::anyhow!("没有可用的代理: {:?} {}", scheme, selector));
+        }
+
+        let index = index.read().await;
+        Ok(candidates[(*index + 1) % candidates.len()].clone())
+    }
+}
+
+// 并发检测代理，按输入顺序返回每个代理的检测结果，progress 为 true 时显示进度条
+pub async fn check(proxy_list: Vec<Proxy>, progress: bool) -> Vec<(Proxy, Result<bool>)> {
+    let total = proxy_list.len();
+
+    // 创建进度条
+    let pb = if progress {
+        let pb = ProgressBar::new(total as u64);
+        pb.set_style(
+            ProgressStyle::default_bar()
+                .template(
+                    "{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} ({eta})",
+                )
+                .unwrap()
+                .progress_chars("#>-"),
+        );
+        Some(Arc::new(pb))
+    } else {
+        None
+    };
+
+    // 创建信号量控制并发数
+    let semaphore = Arc::new(tokio::sync::Semaphore::new(CONFIG.proxy.max_test_count));
+    let mut handles = Vec::with_capacity(total);
+
+    for mut proxy in proxy_list {
+        let semaphore = semaphore.clone();
+        let pb = pb.clone();
+
+        let handle = tokio::spawn(async move {
+            // 获取信号量许可
+            let _permit = semaphore.acquire().await.unwrap();
+
+            // 测试代理
+            let result = proxy.test().await;
+            METRICS.health_check(matches!(result, Ok(true)));
+
+            // 更新进度条
+            if let Some(pb) = &pb {
+                pb.inc(1);
             }
-        };
-        Ok(proxy)
+            (proxy, result)
+        });
+
+        handles.push(handle);
+    }
+
+    // 等待所有测试完成
+    let mut results = Vec::with_capacity(total);
+    for handle in handles {
+        if let Ok(result) = handle.await {
+            results.push(result);
+        }
+    }
+
+    // 结束进度条
+    if let Some(pb) = pb {
+        pb.finish_with_message("测试完成");
     }
+    results
 }
 
 pub fn init() -> Result<Arc<ProxyPool>> {
-    let proxy_pool = Arc::new(ProxyPool::new());
-    Ok(proxy_pool)
+    let mut proxy_pool = ProxyPool::new();
+    proxy_pool.providers = Provider::load()?;
+    Ok(Arc::new(proxy_pool))
 }
 
 // 全局访问config
@@ -0,0 +1,10 @@
+use std::time::Instant;
+
+// 客户端报告的上游故障，冷却期内不再选择该上游
+#[derive(Debug, Clone)]
+pub struct Penalty {
+    // 冷却期内累计的报告次数，达到 retry_count 时移出代理池
+    pub failures: usize,
+    // 冷却期结束时间
+    pub until: Instant,
+}
@@ -0,0 +1,139 @@
+use std::fmt;
+
+use anyhow::Result;
+
+use crate::common::config::Listener;
+
+use super::{anonymity::Anonymity, model::Proxy};
+
+// 标签选择器，格式为 key=value,key=value，所有标签都匹配时选中
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Selector {
+    pub labels: Vec<(String, String)>,
+    // 最大延迟（毫秒），按检测时记录的 latency 标签过滤
+    pub max_latency: Option<u64>,
+    // 最低匿名级别，按检测时记录的 anonymity 标签过滤
+    pub min_anonymity: Option<Anonymity>,
+}
+
+impl Selector {
+    pub fn new() -> Self {
+        Selector {
+            labels: Vec::new(),
+            max_latency: None,
+            min_anonymity: None,
+        }
+    }
+
+    pub fn from(str: &str) -> Result<Self> {
+        let mut selector = Selector::new();
+        for label in str
+            .split(',')
+            .map(str::trim)
+            .filter(|label| !label.is_empty())
+        {
+            let (key, value) = label
+                .split_once('=')
+                .ok_or_else(|| anyhow::anyhow!("无效的标签选择器: {}", str))?;
+            selector = selector.with(key.trim(), value.trim());
+        }
+        Ok(selector)
+    }
+
+    // 监听器的分组和选择器
+    pub fn for_listener(listener: &Listener) -> Result<Self> {
+        let mut selector = Selector::new();
+        if let Some(group) = &listener.group {
+            selector = selector.with("group", group);
+        }
+        if let Some(str) = &listener.selector {
+            selector = selector.merge(&Selector::from(str)?);
+        }
+        Ok(selector)
+    }
+
+    // 添加标签，已存在的同名标签会被覆盖
+    pub fn with(mut self, key: &str, value: &str) -> Self {
+        self.labels.retain(|(k, _)| k != key);
+        self.labels.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    // 合并另一个选择器，other 中的标签优先
+    pub fn merge(mut self, other: &Selector) -> Self {
+        for (key, value) in &other.labels {
+            self = self.with(key, value);
+        }
+        self.max_latency = other.max_latency.or(self.max_latency);
+        self.min_anonymity = other.min_anonymity.or(self.min_anonymity);
+        self
+    }
+
+    // 用另一个选择器进一步缩小范围，只能添加新的标签，与已有标签冲突时返回错误
+    pub fn narrow(mut self, other: &Selector) -> Result<Self> {
+        for (key, value) in &other.labels {
+            match self.labels.iter().find(|(k, _)| k == key) {
+                Some((_, existing)) if !existing.eq_ignore_ascii_case(value) => {
+                    return Err(anyhow::anyhow!(
+                        "标签 {} 已限定为 {}，不能改为 {}",
+                        key,
+                        existing,
+                        value
+                    ));
+                }
+                Some(_) => {}
+                None => self = self.with(key, value),
+            }
+        }
+        self.max_latency = match (self.max_latency, other.max_latency) {
+            (Some(max), Some(other)) => Some(max.min(other)),
+            (max, other) => max.or(other),
+        };
+        self.min_anonymity = self.min_anonymity.max(other.min_anonymity);
+        Ok(self)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.labels.is_empty() && self.max_latency.is_none() && self.min_anonymity.is_none()
+    }
+
+    pub fn matches(&self, proxy: &Proxy) -> bool {
+        let labels = self.labels.iter().all(|(key, value)| {
+            proxy
+                .label(key)
+                .is_some_and(|label| label.eq_ignore_ascii_case(value))
+        });
+        // 未检测过延迟的上游不满足延迟条件
+        let latency = self.max_latency.is_none_or(|max| {
+            proxy
+                .label("latency")
+                .and_then(|latency| latency.parse::<u64>().ok())
+                .is_some_and(|latency| latency <= max)
+        });
+        // 未检测过匿名级别的上游同样不满足条件
+        let anonymity = self.min_anonymity.is_none_or(|min| {
+            proxy
+                .label("anonymity")
+                .and_then(|anonymity| Anonymity::from(anonymity).ok())
+                .is_some_and(|anonymity| anonymity >= min)
+        });
+        labels && latency && anonymity
+    }
+}
+
+impl fmt::Display for Selector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut labels: Vec<String> = self
+            .labels
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect();
+        if let Some(max) = self.max_latency {
+            labels.push(format!("latency<={}", max));
+        }
+        if let Some(min) = self.min_anonymity {
+            labels.push(format!("anonymity>={}", min.show()));
+        }
+        write!(f, "{}", labels.join(","))
+    }
+}
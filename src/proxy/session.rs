@@ -0,0 +1,21 @@
+use std::time::{Duration, Instant};
+
+use super::model::Proxy;
+
+// 粘性会话，同一会话在有效期内固定使用同一个上游
+#[derive(Debug, Clone)]
+pub struct Session {
+    // 会话标识，包含用户名，不同用户的同名会话互不影响
+    pub id: String,
+    // 会话有效期
+    pub ttl: Duration,
+    // 立即为会话更换上游
+    pub rotate: bool,
+}
+
+// 会话当前绑定的上游
+#[derive(Debug, Clone)]
+pub struct SessionEntry {
+    pub proxy: Proxy,
+    pub expires: Instant,
+}
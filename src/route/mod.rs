@@ -0,0 +1,12 @@
+use anyhow::Result;
+use model::ROUTER;
+use tracing::info;
+
+pub mod model;
+
+pub async fn init() -> Result<()> {
+    ROUTER.load().await?;
+
+    info!("路由规则加载成功");
+    Ok(())
+}
@@ -0,0 +1,273 @@
+use std::{fs, net::IpAddr, sync::Arc};
+
+use anyhow::Result;
+use ipnet::IpNet;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::Deserialize;
+use tokio::sync::RwLock;
+
+use crate::{common::config::CONFIG, protocol::model::Address};
+
+// 规则文件格式
+#[derive(Debug, Default, Deserialize)]
+pub struct RuleFile {
+    #[serde(default)]
+    pub rule: Vec<RuleConfig>,
+}
+
+// 同一条规则中的各个条件需要同时满足，同一条件中的多个值满足其一即可
+#[derive(Debug, Deserialize)]
+pub struct RuleConfig {
+    #[serde(default)]
+    pub domain_suffix: Vec<String>,
+    #[serde(default)]
+    pub keyword: Vec<String>,
+    #[serde(default)]
+    pub regex: Vec<String>,
+    #[serde(default)]
+    pub cidr: Vec<IpNet>,
+    #[serde(default)]
+    pub port: Vec<u16>,
+    #[serde(default)]
+    pub user: Vec<String>,
+    pub action: RuleAction,
+    // action 为 group 时使用的代理分组
+    pub group: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RuleAction {
+    Direct,
+    Block,
+    Pool,
+    Group,
+}
+
+// 路由结果
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Route {
+    // 直接连接目标
+    Direct,
+    // 拒绝连接
+    Block,
+    // 使用监听器默认的代理池
+    Pool,
+    // 使用指定的代理分组
+    Group(String),
+}
+
+#[derive(Debug)]
+pub struct Rule {
+    pub domain_suffix: Vec<String>,
+    pub keyword: Vec<String>,
+    pub regex: Vec<Regex>,
+    pub cidr: Vec<IpNet>,
+    pub port: Vec<u16>,
+    pub user: Vec<String>,
+    pub route: Route,
+}
+
+impl Rule {
+    pub fn from(config: RuleConfig) -> Result<Self> {
+        let route = match config.action {
+            RuleAction::Direct => Route::Direct,
+            RuleAction::Block => Route::Block,
+            RuleAction::Pool => Route::Pool,
+            RuleAction::Group => Route::Group(
+                config
+                    .group
+                    .ok_or_else(|| anyhow::anyhow!("action 为 group 的规则缺失 group"))?,
+            ),
+        };
+        let regex = config
+            .regex
+            .iter()
+            .map(|regex| Regex::new(regex))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Rule {
+            domain_suffix: config
+                .domain_suffix
+                .into_iter()
+                .map(|suffix| suffix.trim_start_matches('.').to_ascii_lowercase())
+                .collect(),
+            keyword: config
+                .keyword
+                .into_iter()
+                .map(|keyword| keyword.to_ascii_lowercase())
+                .collect(),
+            regex,
+            cidr: config.cidr,
+            port: config.port,
+            user: config.user,
+            route,
+        })
+    }
+
+    pub fn matches(&self, target: &Address, user: Option<&str>) -> bool {
+        let host = target.host.to_ascii_lowercase();
+
+        if !self.domain_suffix.is_empty()
+            && !self
+                .domain_suffix
+                .iter()
+                .any(|suffix| host == *suffix || host.ends_with(&format!(".{}", suffix)))
+        {
+            return false;
+        }
+        if !self.keyword.is_empty() && !self.keyword.iter().any(|keyword| host.contains(keyword)) {
+            return false;
+        }
+        if !self.regex.is_empty() && !self.regex.iter().any(|regex| regex.is_match(&host)) {
+            return false;
+        }
+        // CIDR 只匹配 IP 形式的目标，不做 DNS 解析
+        if !self.cidr.is_empty() {
+            let Ok(ip) = host.parse::<IpAddr>() else {
+                return false;
+            };
+            if !self.cidr.iter().any(|cidr| cidr.contains(&ip)) {
+                return false;
+            }
+        }
+        if !self.port.is_empty() && !self.port.contains(&target.port) {
+            return false;
+        }
+        if !self.user.is_empty() && !user.is_some_and(|user| self.user.iter().any(|u| u == user)) {
+            return false;
+        }
+        true
+    }
+}
+
+pub struct Router {
+    pub rules: Arc<RwLock<Vec<Rule>>>,
+}
+
+impl Default for Router {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Router {
+    pub fn new() -> Self {
+        Self {
+            rules: Arc::new(RwLock::new(Vec::new())),
+        }
+    }
+
+    // 读取路由规则文件，未配置时不使用任何规则
+    pub async fn load(&self) -> Result<()> {
+        let Some(path) = &CONFIG.route.rule_file else {
+            return Ok(());
+        };
+        let content = fs::read_to_string(path)?;
+        let rule_file: RuleFile = toml::from_str(&content)?;
+        let rules = rule_file
+            .rule
+            .into_iter()
+            .map(Rule::from)
+            .collect::<Result<Vec<_>>>()?;
+
+        *self.rules.write().await = rules;
+        Ok(())
+    }
+
+    // 按顺序匹配规则，第一条匹配的规则生效，没有匹配时使用代理池
+    pub async fn route(&self, target: &Address, user: Option<&str>) -> Route {
+        let rules = self.rules.read().await;
+        rules
+            .iter()
+            .find(|rule| rule.matches(target, user))
+            .map(|rule| rule.route.clone())
+            .unwrap_or(Route::Pool)
+    }
+}
+
+pub fn init() -> Result<Arc<Router>> {
+    let router = Arc::new(Router::new());
+    Ok(router)
+}
+
+// 全局访问路由规则
+pub static ROUTER: Lazy<Arc<Router>> = Lazy::new(|| init().unwrap());
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(toml: &str) -> Rule {
+        let config: RuleConfig = toml::from_str(&format!("{}\naction = \"direct\"", toml)).unwrap();
+        Rule::from(config).unwrap()
+    }
+
+    fn matches(rule: &Rule, target: &str, user: Option<&str>) -> bool {
+        rule.matches(&Address::parse(target, 443).unwrap(), user)
+    }
+
+    #[test]
+    fn domain_suffix() {
+        let rule = parse(r#"domain_suffix = [".Example.com"]"#);
+        assert!(matches(&rule, "example.com", None));
+        assert!(matches(&rule, "api.EXAMPLE.com", None));
+        assert!(!matches(&rule, "badexample.com", None));
+        assert!(!matches(&rule, "example.com.evil.net", None));
+    }
+
+    #[test]
+    fn keyword_and_regex() {
+        let rule = parse(r#"keyword = ["ads"]"#);
+        assert!(matches(&rule, "ads.example.com", None));
+        assert!(matches(&rule, "myADS.net", None));
+        assert!(!matches(&rule, "example.com", None));
+
+        let rule = parse(r#"regex = ['^api\d+\.example\.org$']"#);
+        assert!(matches(&rule, "api1.example.org", None));
+        assert!(!matches(&rule, "api.example.org", None));
+    }
+
+    #[test]
+    fn cidr_matches_ip_targets_only() {
+        let rule = parse(r#"cidr = ["10.0.0.0/8", "2001:db8::/32"]"#);
+        assert!(matches(&rule, "10.1.2.3", None));
+        assert!(matches(&rule, "[2001:db8::1]", None));
+        assert!(!matches(&rule, "11.0.0.1", None));
+        assert!(!matches(&rule, "localhost", None));
+    }
+
+    #[test]
+    fn all_conditions_must_match() {
+        let rule = parse(
+            r#"
+            domain_suffix = ["example.com"]
+            port = [443, 8443]
+            user = ["alice"]
+            "#,
+        );
+        assert!(matches(&rule, "example.com:8443", Some("alice")));
+        assert!(!matches(&rule, "example.com:80", Some("alice")));
+        assert!(!matches(&rule, "example.com:443", Some("bob")));
+        assert!(!matches(&rule, "example.com:443", None));
+        assert!(!matches(&rule, "example.org:443", Some("alice")));
+    }
+
+    #[test]
+    fn empty_rule_matches_everything() {
+        let rule = parse("");
+        assert!(matches(&rule, "example.com", None));
+        assert!(matches(&rule, "10.0.0.1:22", Some("alice")));
+    }
+
+    #[test]
+    fn group_requires_name() {
+        let config: RuleConfig = toml::from_str(r#"action = "group""#).unwrap();
+        assert!(Rule::from(config).is_err());
+        let config: RuleConfig = toml::from_str("action = \"group\"\ngroup = \"res\"").unwrap();
+        assert_eq!(
+            Rule::from(config).unwrap().route,
+            Route::Group("res".to_string())
+        );
+    }
+}
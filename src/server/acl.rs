@@ -0,0 +1,92 @@
+use std::{
+    net::IpAddr,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+use anyhow::Result;
+use ipnet::IpNet;
+use once_cell::sync::Lazy;
+use tokio::sync::RwLock;
+use tracing::{error, info};
+
+use crate::common::{
+    config::{self, CONFIG},
+    logger,
+};
+
+// 客户端 IP 访问控制，deny 优先，allow 为空时允许所有客户端
+#[derive(Debug, Default)]
+pub struct Acl {
+    pub allow: Arc<RwLock<Vec<IpNet>>>,
+    pub deny: Arc<RwLock<Vec<IpNet>>>,
+    // 被拒绝的连接数
+    pub rejected: AtomicU64,
+}
+
+impl Acl {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // 使用启动时的配置
+    pub async fn load(&self) {
+        *self.allow.write().await = CONFIG.server.allow.clone();
+        *self.deny.write().await = CONFIG.server.deny.clone();
+    }
+
+    // 重新读取配置文件中的 allow / deny
+    pub async fn reload(&self) -> Result<()> {
+        let config = config::read()?;
+        *self.allow.write().await = config.server.allow;
+        *self.deny.write().await = config.server.deny;
+        info!("访问控制列表已重新加载");
+        Ok(())
+    }
+
+    pub async fn check(&self, ip: IpAddr) -> bool {
+        // IPv4 映射的 IPv6 地址按 IPv4 匹配
+        let ip = ip.to_canonical();
+        if self.deny.read().await.iter().any(|net| net.contains(&ip)) {
+            return false;
+        }
+        let allow = self.allow.read().await;
+        allow.is_empty() || allow.iter().any(|net| net.contains(&ip))
+    }
+
+    pub fn reject(&self) -> u64 {
+        self.rejected.fetch_add(1, Ordering::Relaxed) + 1
+    }
+}
+
+// 收到 SIGHUP 时重新加载访问控制列表和日志级别
+#[cfg(unix)]
+pub async fn watch() -> Result<()> {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    let mut hangup = signal(SignalKind::hangup())?;
+    while hangup.recv().await.is_some() {
+        if let Err(e) = ACL.reload().await {
+            error!("重新加载访问控制列表失败: {}", e);
+        }
+        if let Err(e) = logger::reload() {
+            error!("重新加载日志级别失败: {}", e);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub async fn watch() -> Result<()> {
+    Ok(())
+}
+
+pub fn init() -> Result<Arc<Acl>> {
+    let acl = Arc::new(Acl::new());
+    Ok(acl)
+}
+
+// 全局访问客户端访问控制列表
+pub static ACL: Lazy<Arc<Acl>> = Lazy::new(|| init().unwrap());
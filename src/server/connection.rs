@@ -0,0 +1,130 @@
+use anyhow::Result;
+use tokio::io::{AsyncRead, AsyncWrite, BufReader};
+use tracing::{error, info, warn};
+
+use crate::{
+    access::model::{ACCESS_LOG, Record},
+    common::config::{ListenerMode, ListenerProtocol},
+    metrics::model::METRICS,
+    protocol::{
+        http::http_proxy,
+        model::{Context, Protocol},
+        socks5::socks5_proxy,
+        transparent::transparent_proxy,
+    },
+    server::acl::ACL,
+    util::{check_proxy_protocol, read_proxy_header},
+};
+
+pub async fn handle_connection<S>(stream: S, mut ctx: Context) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    // 握手超时、空闲超时或超过最大存活时间时关闭连接
+    let activity = ctx.activity.clone();
+    let client_address = ctx.client_address;
+    let result = tokio::select! {
+        res = dispatch(stream, &mut ctx) => res,
+        reason = activity.expired() => {
+            match &ctx.target {
+                Some(target) => info!("关闭连接: {} -> {} - {}", client_address, target, reason),
+                None => info!("关闭连接: {} - {}", client_address, reason),
+            }
+            ctx.reason = Some(reason.to_string());
+            Ok(())
+        }
+    };
+    if let Err(e) = &result {
+        ctx.reason.get_or_insert_with(|| e.to_string());
+    }
+
+    // 请求了目标地址的连接记录访问日志
+    if ctx.target.is_some() {
+        ACCESS_LOG.write(Record::new(&ctx));
+    }
+    result
+}
+
+async fn dispatch<S>(stream: S, ctx: &mut Context) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    // 通过缓冲区预读数据来识别协议
+    let mut stream = BufReader::new(stream);
+
+    // 负载均衡器转发的连接，从 PROXY 协议头中恢复真实的客户端地址
+    let mut proxied = false;
+    if ctx.listener.proxy_protocol
+        && let Some((source, destination)) = read_proxy_header(&mut stream).await?
+    {
+        info!("PROXY 协议: {} -> {}", ctx.client_address, source);
+        ctx.client_address = source;
+        ctx.local_address = destination;
+        proxied = true;
+    }
+
+    // 按真实的客户端地址做访问控制，Unix 套接字的客户端为本机，只检查 PROXY 协议头中的地址
+    if (ctx.listener.path.is_none() || proxied) && !ACL.check(ctx.client_address.ip()).await {
+        let rejected = ACL.reject();
+        METRICS.reject("unknown", "acl");
+        warn!("拒绝客户端连接: {} (累计 {})", ctx.client_address, rejected);
+        ctx.reason = Some("acl".to_string());
+        return Ok(());
+    }
+
+    // 透明代理不需要识别协议，直接转发到原始目标
+    if ctx.listener.mode == ListenerMode::Transparent {
+        ctx.inbound = "transparent";
+        let _active = METRICS.accept(ctx.inbound);
+        let (mut reader, mut writer) = tokio::io::split(stream);
+        if let Err(e) = transparent_proxy(&mut reader, &mut writer, ctx).await {
+            error!("处理透明代理出错: {}", e);
+            ctx.reason.get_or_insert_with(|| e.to_string());
+        }
+        return Ok(());
+    }
+
+    let source_connect_protocol = check_proxy_protocol(&mut stream).await?;
+    info!("代理协议为: {:?}", source_connect_protocol);
+    ctx.inbound = match source_connect_protocol {
+        Protocol::Http => "http",
+        Protocol::Socks5 => "socks5",
+    };
+
+    // 检查监听器是否允许该协议
+    let listener = &ctx.listener;
+    let allowed = matches!(
+        (listener.protocol, &source_connect_protocol),
+        (ListenerProtocol::Auto, _)
+            | (ListenerProtocol::Http, Protocol::Http)
+            | (ListenerProtocol::Socks5, Protocol::Socks5)
+    );
+    if !allowed {
+        METRICS.reject(ctx.inbound, "protocol");
+        return Err(anyhow::anyhow!(
+            "监听器 {} 不允许 {:?} 协议",
+            listener.name,
+            source_connect_protocol
+        ));
+    }
+
+    let _active = METRICS.accept(ctx.inbound);
+    let (mut reader, mut writer) = tokio::io::split(stream);
+    match source_connect_protocol {
+        Protocol::Http => {
+            // 处理 HTTP 请求
+            if let Err(e) = http_proxy(&mut reader, &mut writer, ctx).await {
+                error!("处理 HTTP 请求出错: {}", e);
+                ctx.reason.get_or_insert_with(|| e.to_string());
+            }
+        }
+        Protocol::Socks5 => {
+            // 处理 SOCKS5 请求
+            if let Err(e) = socks5_proxy(&mut reader, &mut writer, ctx).await {
+                error!("处理 SOCKS5 请求出错: {}", e);
+                ctx.reason.get_or_insert_with(|| e.to_string());
+            }
+        }
+    }
+    Ok(())
+}
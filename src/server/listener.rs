@@ -0,0 +1,224 @@
+use std::{
+    net::{Ipv4Addr, SocketAddr},
+    sync::Arc,
+};
+
+use anyhow::Result;
+use socket2::{Domain, Socket, Type};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpListener,
+};
+use tracing::{error, info, warn};
+
+use crate::{
+    common::config::{Listener, ListenerMode},
+    protocol::model::{Address, Context},
+    util::original_destination,
+};
+
+use super::{
+    handle_connection,
+    port_map::{self, PortMap},
+    shutdown,
+};
+
+pub async fn run(listener: Arc<Listener>) -> Result<()> {
+    if let Some(path) = &listener.path {
+        return serve_unix(path.clone(), listener).await;
+    }
+
+    match listener.mode {
+        ListenerMode::Proxy => {
+            let tcp_listener = bind(&listener, listener.port).await?;
+            info!(
+                "监听器 {} 启动在: {}",
+                listener.name,
+                tcp_listener.local_addr()?
+            );
+            serve(tcp_listener, listener, None).await
+        }
+        ListenerMode::PortMap => port_map::run(listener).await,
+        ListenerMode::Transparent => {
+            let tcp_listener = bind(&listener, listener.port).await?;
+            info!(
+                "监听器 {} 透明代理启动在: {}",
+                listener.name,
+                tcp_listener.local_addr()?
+            );
+            serve(tcp_listener, listener, None).await
+        }
+    }
+}
+
+// 绑定 TCP 监听地址
+// IPv6 地址显式设置 IPV6_V6ONLY，TPROXY 需要在绑定前设置 IP_TRANSPARENT
+pub async fn bind(listener: &Listener, port: u16) -> Result<TcpListener> {
+    let address = tokio::net::lookup_host((listener.host.as_str(), port))
+        .await?
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("无法解析监听地址: {}", listener.host))?;
+
+    let socket = Socket::new(Domain::for_address(address), Type::STREAM, None)?;
+    socket.set_reuse_address(true)?;
+    if address.is_ipv6() {
+        socket.set_only_v6(listener.ipv6_only)?;
+    }
+    if listener.mode == ListenerMode::Transparent && listener.tproxy {
+        #[cfg(target_os = "linux")]
+        socket.set_ip_transparent(true)?;
+        #[cfg(not(target_os = "linux"))]
+        return Err(anyhow::anyhow!("TPROXY 仅支持 Linux"));
+    }
+    socket.bind(&address.into())?;
+    socket.listen(1024)?;
+    socket.set_nonblocking(true)?;
+    Ok(TcpListener::from_std(socket.into())?)
+}
+
+// 接受连接并交给 handle_connection 处理
+pub async fn serve(
+    tcp_listener: TcpListener,
+    listener: Arc<Listener>,
+    port_map: Option<Arc<PortMap>>,
+) -> Result<()> {
+    let local_address = tcp_listener.local_addr()?;
+    let local_port = local_address.port();
+
+    loop {
+        let accepted = tokio::select! {
+            accepted = tcp_listener.accept() => accepted,
+            _ = shutdown::stopped() => {
+                info!("监听器 {} 停止接受新连接: {}", listener.name, local_address);
+                return Ok(());
+            }
+        };
+        match accepted {
+            Ok((source_stream, source_address)) => {
+                info!("接受到新连接: {}", source_address);
+                let local_address = source_stream.local_addr().unwrap_or(local_address);
+                let mut ctx = Context::new(listener.clone(), source_address, local_address);
+                if let Some(port_map) = &port_map {
+                    ctx.fixed_upstream = port_map.get(local_port).await;
+                    if ctx.fixed_upstream.is_none() {
+                        warn!("端口 {} 没有映射的上游，关闭连接", local_port);
+                        continue;
+                    }
+                }
+                if listener.mode == ListenerMode::Transparent {
+                    match original_destination(&source_stream, listener.tproxy) {
+                        Ok(destination) => {
+                            ctx.target = Some(Address::new(
+                                destination.ip().to_string(),
+                                destination.port(),
+                            ));
+                        }
+                        Err(e) => {
+                            error!("透明代理连接出错: {} - {}", source_address, e);
+                            continue;
+                        }
+                    }
+                }
+                spawn(source_stream, ctx);
+            }
+            Err(e) => {
+                error!("接受连接失败: {}", e);
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+async fn serve_unix(path: String, listener: Arc<Listener>) -> Result<()> {
+    let unix_listener = bind_unix(&path, listener.permissions.as_deref())?;
+    info!("监听器 {} 启动在: unix:{}", listener.name, path);
+
+    // Unix 套接字的客户端视为本机
+    let local_address = SocketAddr::from((Ipv4Addr::LOCALHOST, 0));
+    loop {
+        let accepted = tokio::select! {
+            accepted = unix_listener.accept() => accepted,
+            _ = shutdown::stopped() => {
+                info!("监听器 {} 停止接受新连接: unix:{}", listener.name, path);
+                return Ok(());
+            }
+        };
+        match accepted {
+            Ok((source_stream, _)) => {
+                info!("接受到新连接: unix:{}", path);
+                let ctx = Context::new(listener.clone(), local_address, local_address);
+                spawn(source_stream, ctx);
+            }
+            Err(e) => {
+                error!("接受连接失败: {}", e);
+            }
+        }
+    }
+}
+
+// 在权限为 700 的临时目录中绑定套接字并设置权限，再移动到配置的路径，
+// 避免套接字在设置权限之前以 umask 决定的权限暴露
+#[cfg(unix)]
+fn bind_unix(path: &str, permissions: Option<&str>) -> Result<tokio::net::UnixListener> {
+    use std::{
+        fs::{self, DirBuilder},
+        os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt},
+        path::Path,
+    };
+
+    // 只删除上次运行遗留的套接字文件，路径被其他文件占用时报错
+    if let Ok(metadata) = fs::symlink_metadata(path) {
+        if !metadata.file_type().is_socket() {
+            return Err(anyhow::anyhow!("路径已存在且不是套接字: {}", path));
+        }
+        fs::remove_file(path)?;
+    }
+    let mode = permissions
+        .map(|permissions| {
+            u32::from_str_radix(permissions, 8)
+                .map_err(|_| anyhow::anyhow!("无效的套接字权限: {}", permissions))
+        })
+        .transpose()?;
+
+    let target = Path::new(path);
+    let name = target
+        .file_name()
+        .ok_or_else(|| anyhow::anyhow!("无效的套接字路径: {}", path))?;
+    let staging = target.with_file_name(format!(
+        ".{}.{}",
+        name.to_string_lossy(),
+        std::process::id()
+    ));
+    DirBuilder::new().mode(0o700).create(&staging)?;
+    let staged = staging.join("socket");
+    let bound = tokio::net::UnixListener::bind(&staged)
+        .map_err(anyhow::Error::from)
+        .and_then(|unix_listener| {
+            if let Some(mode) = mode {
+                fs::set_permissions(&staged, fs::Permissions::from_mode(mode))?;
+            }
+            fs::rename(&staged, target)?;
+            Ok(unix_listener)
+        });
+    _ = fs::remove_file(&staged);
+    _ = fs::remove_dir(&staging);
+    bound
+}
+
+#[cfg(not(unix))]
+async fn serve_unix(_path: String, _listener: Arc<Listener>) -> Result<()> {
+    Err(anyhow::anyhow!("当前平台不支持 Unix 套接字"))
+}
+
+fn spawn<S>(stream: S, ctx: Context)
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let guard = shutdown::Guard::new();
+    tokio::spawn(async move {
+        let _guard = guard;
+        if let Err(e) = handle_connection(stream, ctx).await {
+            error!("连接处理出错: {}", e);
+        }
+    });
+}
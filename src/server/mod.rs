@@ -0,0 +1,41 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use tracing::error;
+
+use crate::common::config::CONFIG;
+
+pub mod acl;
+mod connection;
+mod listener;
+pub mod port_map;
+pub mod shutdown;
+
+pub use connection::handle_connection;
+pub use shutdown::shutdown;
+
+// 启动所有监听器
+pub async fn run() -> Result<()> {
+    acl::ACL.load().await;
+    tokio::spawn(async {
+        if let Err(e) = acl::watch().await {
+            error!("访问控制列表错误: {}", e);
+        }
+    });
+
+    let mut handles = Vec::new();
+    for listener in CONFIG.listeners() {
+        let listener = Arc::new(listener);
+        handles.push(tokio::spawn(async move {
+            let name = listener.name.clone();
+            if let Err(e) = listener::run(listener).await {
+                error!("监听器 {} 错误: {}", name, e);
+            }
+        }));
+    }
+
+    for handle in handles {
+        handle.await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,129 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    ops::RangeInclusive,
+    sync::Arc,
+};
+
+use anyhow::Result;
+use once_cell::sync::Lazy;
+use tokio::sync::RwLock;
+use tracing::{error, info};
+
+use crate::{
+    common::config::Listener,
+    proxy::{
+        model::{PROXY_POOL, Proxy},
+        selector::Selector,
+    },
+};
+
+use super::{
+    listener::{bind, serve},
+    shutdown,
+};
+
+// 所有端口映射监听器，供 API 查询
+pub static PORT_MAPS: Lazy<RwLock<Vec<Arc<PortMap>>>> = Lazy::new(|| RwLock::new(Vec::new()));
+
+pub struct PortMap {
+    pub listener: Arc<Listener>,
+    pub table: RwLock<BTreeMap<u16, Proxy>>,
+}
+
+impl PortMap {
+    pub fn new(listener: Arc<Listener>) -> Self {
+        PortMap {
+            listener,
+            table: RwLock::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn ports(&self) -> RangeInclusive<u16> {
+        let start = self.listener.port;
+        start..=self.listener.port_end.unwrap_or(start)
+    }
+
+    pub async fn get(&self, port: u16) -> Option<Proxy> {
+        self.table.read().await.get(&port).cloned()
+    }
+
+    // 根据代理池重建映射: 仍然可用的上游保持原端口，失效上游的端口分配给未映射的上游
+    pub async fn rebuild(&self) -> Result<()> {
+        let selector = Selector::for_listener(&self.listener)?;
+        let proxies = PROXY_POOL.all(&selector).await;
+        let mut alive: HashMap<String, Proxy> = proxies
+            .iter()
+            .map(|proxy| (proxy.show(), proxy.clone()))
+            .collect();
+
+        let mut table = self.table.write().await;
+        table.retain(|_, proxy| alive.contains_key(&proxy.show()));
+        for proxy in table.values_mut() {
+            if let Some(latest) = alive.remove(&proxy.show()) {
+                *proxy = latest;
+            }
+        }
+
+        let used: HashSet<String> = table.values().map(|proxy| proxy.show()).collect();
+        let mut free = proxies
+            .into_iter()
+            .filter(|proxy| !used.contains(&proxy.show()));
+        for port in self.ports() {
+            if table.contains_key(&port) {
+                continue;
+            }
+            match free.next() {
+                Some(proxy) => {
+                    table.insert(port, proxy);
+                }
+                None => break,
+            }
+        }
+        Ok(())
+    }
+}
+
+pub async fn run(listener: Arc<Listener>) -> Result<()> {
+    let port_map = Arc::new(PortMap::new(listener.clone()));
+    PORT_MAPS.write().await.push(port_map.clone());
+
+    // 先订阅再构建，避免错过构建期间的变化
+    let mut changed = PROXY_POOL.changed.subscribe();
+    port_map.rebuild().await?;
+
+    for port in port_map.ports() {
+        let tcp_listener = bind(&listener, port).await?;
+        let listener = listener.clone();
+        let port_map = port_map.clone();
+        tokio::spawn(async move {
+            if let Err(e) = serve(tcp_listener, listener, Some(port_map)).await {
+                error!("端口映射监听出错: {} - {}", port, e);
+            }
+        });
+    }
+    info!(
+        "监听器 {} 端口映射启动在: {}:{}-{}",
+        listener.name,
+        listener.host,
+        port_map.ports().start(),
+        port_map.ports().end()
+    );
+
+    // 代理池变化时重建映射，关闭服务时退出
+    loop {
+        tokio::select! {
+            res = changed.changed() => if res.is_err() { break },
+            _ = shutdown::stopped() => break,
+        }
+        if let Err(e) = port_map.rebuild().await {
+            error!("监听器 {} 端口映射更新失败: {}", listener.name, e);
+            continue;
+        }
+        info!(
+            "监听器 {} 端口映射已更新，共映射 {} 个上游",
+            listener.name,
+            port_map.table.read().await.len()
+        );
+    }
+    Ok(())
+}
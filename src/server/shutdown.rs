@@ -0,0 +1,71 @@
+use std::{
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use once_cell::sync::Lazy;
+use tokio::sync::{Notify, watch};
+use tracing::{info, warn};
+
+use crate::common::config::CONFIG;
+
+// 关闭服务的通知，监听器收到后停止接受新连接
+static SHUTDOWN: Lazy<watch::Sender<bool>> = Lazy::new(|| watch::channel(false).0);
+
+// 正在处理的连接数，归零时通知等待关闭的任务
+static CONNECTIONS: AtomicUsize = AtomicUsize::new(0);
+static DRAINED: Lazy<Notify> = Lazy::new(Notify::new);
+
+// 连接处理期间持有，结束时减少连接数
+pub struct Guard;
+
+impl Guard {
+    pub fn new() -> Self {
+        CONNECTIONS.fetch_add(1, Ordering::SeqCst);
+        Guard
+    }
+}
+
+impl Default for Guard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        if CONNECTIONS.fetch_sub(1, Ordering::SeqCst) == 1 {
+            DRAINED.notify_waiters();
+        }
+    }
+}
+
+pub fn connections() -> usize {
+    CONNECTIONS.load(Ordering::SeqCst)
+}
+
+// 等待关闭服务的通知
+pub async fn stopped() {
+    let mut shutdown = SHUTDOWN.subscribe();
+    let _ = shutdown.wait_for(|stopped| *stopped).await;
+}
+
+// 停止接受新连接，等待已有连接结束，最多等待 [timeout] shutdown 秒
+pub async fn shutdown() {
+    SHUTDOWN.send_replace(true);
+
+    let timeout = Duration::from_secs(CONFIG.timeout.shutdown);
+    let drained = async {
+        loop {
+            let notified = DRAINED.notified();
+            if connections() == 0 {
+                return;
+            }
+            info!("等待 {} 个连接结束...", connections());
+            notified.await;
+        }
+    };
+    if tokio::time::timeout(timeout, drained).await.is_err() {
+        warn!("等待连接结束超时，强制关闭 {} 个连接", connections());
+    }
+}
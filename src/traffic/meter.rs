@@ -0,0 +1,132 @@
+use std::{
+    io,
+    pin::Pin,
+    task::{Context as TaskContext, Poll, ready},
+    time::{Duration, Instant},
+};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use crate::{
+    metrics::model::METRICS,
+    protocol::{model::Context, upstream::Outbound},
+    proxy::model::PROXY_POOL,
+};
+
+use super::model::TRAFFIC;
+
+// 连接内累计到该字节数或时间间隔后才写入共享的流量统计
+const FLUSH_BYTES: u64 = 64 * 1024;
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+// 统计经过上游连接的流量，写入为上传，读取为下载
+// 任意一个 key 的配额用完时返回错误，中断隧道
+pub struct Metered<S> {
+    inner: S,
+    keys: Vec<String>,
+    quotas: Vec<(String, u64)>,
+    // 尚未写入流量统计的字节数
+    upload: u64,
+    download: u64,
+    flushed: Instant,
+}
+
+impl<S> Metered<S> {
+    // 流量计入认证用户、上游以及上游所属的服务商
+    pub fn new(inner: S, ctx: &Context, outbound: &Outbound) -> Self {
+        let mut keys = Vec::new();
+        if let Some(user) = &ctx.user {
+            keys.push(format!("user:{}", user));
+        }
+        if let Outbound::Proxy(proxy) = outbound {
+            keys.push(format!("proxy:{}", proxy.show()));
+            for provider in PROXY_POOL
+                .providers
+                .iter()
+                .filter(|provider| provider.selector.matches(proxy))
+            {
+                keys.push(format!("provider:{}", provider.name));
+            }
+        }
+        let quotas = keys
+            .iter()
+            .filter_map(|key| TRAFFIC.quota(key).map(|quota| (key.clone(), quota)))
+            .collect();
+        Metered {
+            inner,
+            keys,
+            quotas,
+            upload: 0,
+            download: 0,
+            flushed: Instant::now(),
+        }
+    }
+
+    fn record(&mut self, upload: u64, download: u64) -> io::Result<()> {
+        METRICS.relay(upload, download);
+        self.upload += upload;
+        self.download += download;
+        if self.upload + self.download < FLUSH_BYTES && self.flushed.elapsed() < FLUSH_INTERVAL {
+            return Ok(());
+        }
+        self.flush()
+    }
+
+    // 写入累计的流量并检查配额
+    fn flush(&mut self) -> io::Result<()> {
+        let upload = std::mem::take(&mut self.upload);
+        let download = std::mem::take(&mut self.download);
+        self.flushed = Instant::now();
+        if upload + download == 0 {
+            return Ok(());
+        }
+        match TRAFFIC.record(&self.keys, upload, download, &self.quotas) {
+            Some(key) => Err(io::Error::other(format!("流量配额已用完: {}", key))),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<S> Drop for Metered<S> {
+    fn drop(&mut self) {
+        _ = self.flush();
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Metered<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let filled = buf.filled().len();
+        ready!(Pin::new(&mut self.inner).poll_read(cx, buf))?;
+        let read = (buf.filled().len() - filled) as u64;
+        if read > 0 {
+            self.record(0, read)?;
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Metered<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let written = ready!(Pin::new(&mut self.inner).poll_write(cx, buf))?;
+        if written > 0 {
+            self.record(written as u64, 0)?;
+        }
+        Poll::Ready(Ok(written))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
@@ -0,0 +1,29 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use model::TRAFFIC;
+use tracing::{error, info};
+
+use crate::common::config::CONFIG;
+
+pub mod meter;
+pub mod model;
+
+pub async fn init() -> Result<()> {
+    TRAFFIC.load().await?;
+
+    info!("流量统计加载成功");
+    Ok(())
+}
+
+// 定期将流量统计写入文件
+pub async fn run() -> Result<()> {
+    let mut interval = tokio::time::interval(Duration::from_secs(CONFIG.traffic.save_interval));
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+        if let Err(e) = TRAFFIC.save().await {
+            error!("保存流量统计失败: {}", e);
+        }
+    }
+}
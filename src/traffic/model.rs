@@ -0,0 +1,325 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::Path,
+    sync::{
+        Arc, Mutex, MutexGuard,
+        atomic::{AtomicI64, Ordering},
+    },
+};
+
+use anyhow::Result;
+use chrono::{DateTime, Datelike, NaiveDate, Utc};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    common::config::{CONFIG, Config, TrafficPeriod},
+    util::parse_size,
+};
+
+// 单个用户或上游的流量
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize)]
+pub struct Counter {
+    pub upload: u64,
+    pub download: u64,
+}
+
+impl Counter {
+    pub fn total(&self) -> u64 {
+        self.upload + self.download
+    }
+}
+
+// 当前统计周期内的流量，写入文件的格式
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct Counters {
+    // 统计周期，例如 2026-10 或 2026-10-18
+    pub period: String,
+    // key 为 user:用户名、proxy:上游地址 或 provider:服务商
+    pub counters: BTreeMap<String, Counter>,
+}
+
+impl Counters {
+    // 进入新的统计周期时清空计数，返回已结束周期的流量
+    fn rollover(&mut self, period: &str) -> Option<Counters> {
+        if self.period == period {
+            return None;
+        }
+        let closed = std::mem::replace(
+            self,
+            Counters {
+                period: period.to_string(),
+                counters: BTreeMap::new(),
+            },
+        );
+        (!closed.period.is_empty() && !closed.counters.is_empty()).then_some(closed)
+    }
+}
+
+pub struct Traffic {
+    pub counters: Arc<Mutex<Counters>>,
+    // 已结束但尚未归档的统计周期，下次保存时写入 <file>.<period>
+    pub closed: Arc<Mutex<Vec<Counters>>>,
+    pub period: TrafficPeriod,
+    // 用户和服务商的流量配额，启动时解析一次
+    pub quotas: HashMap<String, u64>,
+    // 当前统计周期的结束时间（毫秒时间戳），到达后才重新计算周期
+    ends: AtomicI64,
+}
+
+impl Default for Traffic {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Traffic {
+    pub fn new() -> Self {
+        Self {
+            counters: Arc::new(Mutex::new(Counters::default())),
+            closed: Arc::new(Mutex::new(Vec::new())),
+            period: TrafficPeriod::default(),
+            quotas: HashMap::new(),
+            ends: AtomicI64::new(0),
+        }
+    }
+
+    // 读取上次保存的流量统计，文件不存在时从零开始
+    pub async fn load(&self) -> Result<()> {
+        let path = &CONFIG.traffic.file;
+        if !Path::new(path).exists() {
+            return Ok(());
+        }
+        let content = fs::read_to_string(path)?;
+        let counters: Counters = serde_json::from_str(&content)?;
+        *self.counters.lock().unwrap() = counters;
+        // 文件中是已结束的周期时，在下次保存时归档
+        self.ends.store(0, Ordering::Relaxed);
+        drop(self.lock());
+        Ok(())
+    }
+
+    // 先归档已结束的周期，再写入当前周期，归档失败时保留到下次保存
+    pub async fn save(&self) -> Result<()> {
+        let (closed, current) = {
+            let counters = self.lock();
+            let closed = std::mem::take(&mut *self.closed.lock().unwrap());
+            (closed, counters.clone())
+        };
+        let path = &CONFIG.traffic.file;
+        for (i, counters) in closed.iter().enumerate() {
+            if let Err(e) = write(&format!("{}.{}", path, counters.period), counters) {
+                self.closed
+                    .lock()
+                    .unwrap()
+                    .splice(0..0, closed[i..].iter().cloned());
+                return Err(e);
+            }
+        }
+        write(path, &current)
+    }
+
+    pub fn snapshot(&self) -> Counters {
+        self.lock().clone()
+    }
+
+    // 记录流量，同一份流量计入所有 key，返回第一个配额已用完的 key
+    pub fn record<'a>(
+        &self,
+        keys: &[String],
+        upload: u64,
+        download: u64,
+        quotas: &'a [(String, u64)],
+    ) -> Option<&'a str> {
+        let mut counters = self.lock();
+        for key in keys {
+            let counter = counters.counters.entry(key.clone()).or_default();
+            counter.upload += upload;
+            counter.download += download;
+        }
+        quotas
+            .iter()
+            .find(|(key, quota)| {
+                counters
+                    .counters
+                    .get(key)
+                    .is_some_and(|counter| counter.total() >= *quota)
+            })
+            .map(|(key, _)| key.as_str())
+    }
+
+    // 当前周期内已使用的流量
+    pub fn used(&self, key: &str) -> u64 {
+        self.lock()
+            .counters
+            .get(key)
+            .map(Counter::total)
+            .unwrap_or_default()
+    }
+
+    // 锁定当前周期的计数，到达周期结束时间时进入新周期，已结束的周期留待归档
+    fn lock(&self) -> MutexGuard<'_, Counters> {
+        let now = Utc::now();
+        let mut counters = self.counters.lock().unwrap();
+        if now.timestamp_millis() >= self.ends.load(Ordering::Relaxed) {
+            let (period, ends) = period(now, self.period);
+            self.ends.store(ends.timestamp_millis(), Ordering::Relaxed);
+            if let Some(closed) = counters.rollover(&period) {
+                self.closed.lock().unwrap().push(closed);
+            }
+        }
+        counters
+    }
+
+    // 流量配额，来自用户和服务商的 quota 配置
+    pub fn quota(&self, key: &str) -> Option<u64> {
+        self.quotas.get(key).copied()
+    }
+
+    // 是否已用完配额，没有配额时返回 false
+    pub fn exceeded(&self, key: &str) -> bool {
+        self.quota(key).is_some_and(|quota| self.used(key) >= quota)
+    }
+}
+
+// 解析用户和服务商的流量配额，key 与流量统计相同
+fn quotas(config: &Config) -> Result<HashMap<String, u64>> {
+    let users = config
+        .user
+        .iter()
+        .filter_map(|user| Some((format!("user:{}", user.username), user.quota.as_ref()?)));
+    let providers = config.provider.iter().filter_map(|provider| {
+        Some((
+            format!("provider:{}", provider.name),
+            provider.quota.as_ref()?,
+        ))
+    });
+    users
+        .chain(providers)
+        .map(|(key, quota)| Ok((key, parse_size(quota)?)))
+        .collect()
+}
+
+// 先写入临时文件再重命名，避免写入中断时损坏原文件
+fn write(path: &str, counters: &Counters) -> Result<()> {
+    let content = serde_json::to_string_pretty(counters)?;
+    let temp = format!("{}.tmp", path);
+    fs::write(&temp, content)?;
+    fs::rename(&temp, path)?;
+    Ok(())
+}
+
+// 时间所在的统计周期及其结束时间，按 UTC 时间计算
+fn period(now: DateTime<Utc>, period: TrafficPeriod) -> (String, DateTime<Utc>) {
+    let today = now.date_naive();
+    let (key, next) = match period {
+        TrafficPeriod::Daily => (today.format("%Y-%m-%d").to_string(), today.succ_opt()),
+        TrafficPeriod::Monthly => {
+            let (year, month) = match today.month() {
+                12 => (today.year() + 1, 1),
+                month => (today.year(), month + 1),
+            };
+            (
+                today.format("%Y-%m").to_string(),
+                NaiveDate::from_ymd_opt(year, month, 1),
+            )
+        }
+    };
+    let ends = next
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+        .map(|time| time.and_utc())
+        .unwrap_or(DateTime::<Utc>::MAX_UTC);
+    (key, ends)
+}
+
+pub fn init() -> Result<Arc<Traffic>> {
+    let mut traffic = Traffic::new();
+    traffic.period = CONFIG.traffic.period;
+    traffic.quotas = quotas(&CONFIG)?;
+    Ok(Arc::new(traffic))
+}
+
+// 全局访问流量统计
+pub static TRAFFIC: Lazy<Arc<Traffic>> = Lazy::new(|| init().unwrap());
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn counters(period: &str, total: u64) -> Counters {
+        Counters {
+            period: period.to_string(),
+            counters: BTreeMap::from([(
+                "user:alice".to_string(),
+                Counter {
+                    upload: total,
+                    download: 0,
+                },
+            )]),
+        }
+    }
+
+    #[test]
+    fn rollover_returns_closed_period() {
+        let mut current = counters("2026-10-17", 100);
+        assert!(current.rollover("2026-10-17").is_none());
+
+        let closed = current.rollover("2026-10-18").unwrap();
+        assert_eq!(closed.period, "2026-10-17");
+        assert_eq!(closed.counters["user:alice"].total(), 100);
+        assert_eq!(current.period, "2026-10-18");
+        assert!(current.counters.is_empty());
+
+        // 空的周期不需要归档
+        assert!(current.rollover("2026-10-19").is_none());
+        assert!(Counters::default().rollover("2026-10-19").is_none());
+    }
+
+    #[test]
+    fn periods() {
+        let time = |str: &str| str.parse::<DateTime<Utc>>().unwrap();
+        assert_eq!(
+            period(time("2026-10-18T23:59:59Z"), TrafficPeriod::Daily),
+            ("2026-10-18".to_string(), time("2026-10-19T00:00:00Z"))
+        );
+        assert_eq!(
+            period(time("2026-10-18T12:00:00Z"), TrafficPeriod::Monthly),
+            ("2026-10".to_string(), time("2026-11-01T00:00:00Z"))
+        );
+        assert_eq!(
+            period(time("2026-12-31T12:00:00Z"), TrafficPeriod::Monthly),
+            ("2026-12".to_string(), time("2027-01-01T00:00:00Z"))
+        );
+    }
+
+    #[test]
+    fn record_checks_cached_quotas() {
+        let mut traffic = Traffic::new();
+        traffic.quotas.insert("user:alice".to_string(), 100);
+        let keys = ["user:alice".to_string(), "proxy:a".to_string()];
+        let quotas = [("user:alice".to_string(), 100)];
+
+        assert_eq!(traffic.record(&keys, 60, 0, &quotas), None);
+        assert!(!traffic.exceeded("user:alice"));
+        assert_eq!(traffic.record(&keys, 0, 40, &quotas), Some("user:alice"));
+        assert!(traffic.exceeded("user:alice"));
+        assert_eq!(traffic.used("proxy:a"), 100);
+        assert!(!traffic.exceeded("proxy:a"));
+    }
+
+    #[test]
+    fn write_replaces_file() {
+        let dir = std::env::temp_dir().join(format!("traffic-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("traffic.json").to_string_lossy().to_string();
+
+        write(&path, &counters("2026-10", 1)).unwrap();
+        write(&path, &counters("2026-10", 2)).unwrap();
+        let saved: Counters = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(saved.counters["user:alice"].total(), 2);
+        assert!(!Path::new(&format!("{}.tmp", path)).exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,152 @@
+use std::{
+    io,
+    pin::Pin,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
+    task::{Context, Poll, ready},
+    time::{Duration, Instant},
+};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use crate::common::config::CONFIG;
+
+// 连接的活动状态，用于握手超时、空闲超时和最大存活时间
+#[derive(Debug, Clone)]
+pub struct Activity {
+    start: Instant,
+    established: Arc<AtomicBool>,
+    // 最近一次读写距离 start 的毫秒数
+    last: Arc<AtomicU64>,
+    // 写入上游和从上游读取的字节数
+    upload: Arc<AtomicU64>,
+    download: Arc<AtomicU64>,
+}
+
+impl Default for Activity {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Activity {
+    pub fn new() -> Self {
+        Activity {
+            start: Instant::now(),
+            established: Arc::new(AtomicBool::new(false)),
+            last: Arc::new(AtomicU64::new(0)),
+            upload: Arc::new(AtomicU64::new(0)),
+            download: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    // 上传和下载的字节数
+    pub fn bytes(&self) -> (u64, u64) {
+        (
+            self.upload.load(Ordering::Relaxed),
+            self.download.load(Ordering::Relaxed),
+        )
+    }
+
+    // 握手完成，开始计算空闲超时
+    pub fn establish(&self) {
+        self.touch();
+        self.established.store(true, Ordering::Relaxed);
+    }
+
+    pub fn touch(&self) {
+        let elapsed = self.start.elapsed().as_millis() as u64;
+        self.last.store(elapsed, Ordering::Relaxed);
+    }
+
+    // 等待连接超时，返回超时原因，配置为 0 的超时不生效
+    pub async fn expired(&self) -> &'static str {
+        let timeout = &CONFIG.timeout;
+        let seconds = |seconds: u64| (seconds > 0).then(|| Duration::from_secs(seconds));
+        loop {
+            let now = Instant::now();
+            let mut deadlines = Vec::new();
+            if !self.established.load(Ordering::Relaxed) {
+                deadlines.extend(seconds(timeout.handshake).map(|d| (self.start + d, "握手超时")));
+            } else {
+                let last = self.start + Duration::from_millis(self.last.load(Ordering::Relaxed));
+                deadlines.extend(seconds(timeout.idle).map(|d| (last + d, "空闲超时")));
+            }
+            deadlines.extend(
+                seconds(timeout.max_lifetime).map(|d| (self.start + d, "超过最大存活时间")),
+            );
+
+            let Some((deadline, reason)) = deadlines.into_iter().min_by_key(|(d, _)| *d) else {
+                // 握手完成且没有配置空闲超时和最大存活时间
+                if self.established.load(Ordering::Relaxed) {
+                    return std::future::pending().await;
+                }
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                continue;
+            };
+            if deadline <= now {
+                return reason;
+            }
+            tokio::time::sleep_until(deadline.into()).await;
+        }
+    }
+}
+
+// 读写时更新活动时间并统计字节数
+pub struct Tracked<S> {
+    inner: S,
+    activity: Activity,
+}
+
+impl<S> Tracked<S> {
+    pub fn new(inner: S, activity: &Activity) -> Self {
+        Tracked {
+            inner,
+            activity: activity.clone(),
+        }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Tracked<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let filled = buf.filled().len();
+        ready!(Pin::new(&mut self.inner).poll_read(cx, buf))?;
+        let read = (buf.filled().len() - filled) as u64;
+        self.activity.download.fetch_add(read, Ordering::Relaxed);
+        self.activity.touch();
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Tracked<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let written = ready!(Pin::new(&mut self.inner).poll_write(cx, buf))?;
+        self.activity
+            .upload
+            .fetch_add(written as u64, Ordering::Relaxed);
+        self.activity.touch();
+        Poll::Ready(Ok(written))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
@@ -1,10 +1,16 @@
 use anyhow::Result;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt};
 
 use crate::protocol::model::Protocol;
 
-pub async fn check_proxy_protocol(stream: &mut tokio::net::TcpStream) -> Result<Protocol> {
-    let mut buf = [0u8; 8];
-    let n = stream.peek(&mut buf).await?;
+// 预读缓冲区中的数据识别协议，不消费数据
+pub async fn check_proxy_protocol<S>(stream: &mut S) -> Result<Protocol>
+where
+    S: AsyncBufRead + Unpin,
+{
+    let peeked = stream.fill_buf().await?;
+    let n = peeked.len().min(8);
+    let buf = &peeked[..n];
 
     if n >= 2 {
         // 1. 检查 SOCKS5 (第一个字节是 0x05)
@@ -12,16 +18,15 @@ pub async fn check_proxy_protocol(stream: &mut tokio::net::TcpStream) -> Result<
             return Ok(Protocol::Socks5);
         }
         // 2. 检查 HTTP (开头是 GET/POST/HEAD 等)
-        else if let Ok(s) = std::str::from_utf8(&buf[..n]) {
-            if s.starts_with("GET ")
+        else if let Ok(s) = std::str::from_utf8(&buf[..n])
+            && (s.starts_with("GET ")
                 || s.starts_with("PUT ")
                 || s.starts_with("POST ")
                 || s.starts_with("HEAD ")
                 || s.starts_with("DELETE ")
-                || s.starts_with("CONNECT ")
-            {
-                return Ok(Protocol::Http);
-            }
+                || s.starts_with("CONNECT "))
+        {
+            return Ok(Protocol::Http);
         }
     }
 
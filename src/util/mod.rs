@@ -1,3 +1,15 @@
+mod activity;
 mod check_proxy_protocol;
+mod original_destination;
+mod parse_size;
+mod proxy_protocol;
+mod relay;
+mod rotate;
 
+pub use activity::{Activity, Tracked};
 pub use check_proxy_protocol::check_proxy_protocol;
+pub use original_destination::original_destination;
+pub use parse_size::parse_size;
+pub use proxy_protocol::{ProxyProtocolVersion, encode_proxy_header, read_proxy_header};
+pub use relay::{Cause, Relayed, relay};
+pub use rotate::RotatingFile;
@@ -0,0 +1,34 @@
+use std::net::SocketAddr;
+
+use anyhow::Result;
+
+// 获取被 iptables 重定向前的原始目标地址
+// REDIRECT 通过 SO_ORIGINAL_DST 获取，TPROXY 下本地地址即为原始目标
+#[cfg(target_os = "linux")]
+pub fn original_destination(stream: &tokio::net::TcpStream, tproxy: bool) -> Result<SocketAddr> {
+    let local_address = stream.local_addr()?;
+    if tproxy {
+        return Ok(local_address);
+    }
+
+    let socket = socket2::SockRef::from(stream);
+    let address = match local_address {
+        SocketAddr::V4(_) => socket.original_dst(),
+        SocketAddr::V6(_) => socket.original_dst_ipv6(),
+    }
+    .map_err(|e| anyhow::anyhow!("获取原始目标地址失败: {}", e))?;
+    let address = address
+        .as_socket()
+        .ok_or_else(|| anyhow::anyhow!("无法解析原始目标地址"))?;
+
+    // 未经过重定向的连接，原始目标就是监听地址本身，继续转发会形成回环
+    if address == local_address {
+        return Err(anyhow::anyhow!("连接未经过重定向: {}", address));
+    }
+    Ok(address)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn original_destination(_stream: &tokio::net::TcpStream, _tproxy: bool) -> Result<SocketAddr> {
+    Err(anyhow::anyhow!("透明代理仅支持 Linux"))
+}
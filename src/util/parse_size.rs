@@ -0,0 +1,50 @@
+use anyhow::Result;
+
+// 解析 500MB / 10GB 形式的大小，按 1024 进制计算，不带单位时为字节
+pub fn parse_size(str: &str) -> Result<u64> {
+    let str = str.trim();
+    let index = str.find(|c: char| !c.is_ascii_digit()).unwrap_or(str.len());
+    let (number, unit) = str.split_at(index);
+    let number: u64 = number
+        .parse()
+        .map_err(|_| anyhow::anyhow!("无效的大小: {}", str))?;
+    let unit: u64 = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" => 1 << 10,
+        "M" | "MB" => 1 << 20,
+        "G" | "GB" => 1 << 30,
+        "T" | "TB" => 1 << 40,
+        _ => return Err(anyhow::anyhow!("无效的大小: {}", str)),
+    };
+    number
+        .checked_mul(unit)
+        .ok_or_else(|| anyhow::anyhow!("大小超出范围: {}", str))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn units() {
+        assert_eq!(parse_size("512").unwrap(), 512);
+        assert_eq!(parse_size("1KB").unwrap(), 1024);
+        assert_eq!(parse_size(" 500 mb ").unwrap(), 500 << 20);
+        assert_eq!(parse_size("10G").unwrap(), 10 << 30);
+        assert_eq!(parse_size("2TB").unwrap(), 2 << 40);
+    }
+
+    #[test]
+    fn invalid() {
+        for str in ["", "MB", "1.5GB", "-1", "10PB", "99999999999999999999"] {
+            assert!(parse_size(str).is_err(), "{}", str);
+        }
+    }
+
+    #[test]
+    fn overflow() {
+        assert_eq!(parse_size("16777215TB").unwrap(), 16777215 << 40);
+        assert!(parse_size("16777216TB").is_err());
+        assert!(parse_size("18446744073709551615KB").is_err());
+    }
+}
@@ -0,0 +1,303 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+const V2_SIGNATURE: [u8; 12] = [
+    0x0D, 0x0A, 0x0D, 0x0A, 0x00, 0x0D, 0x0A, 0x51, 0x55, 0x49, 0x54, 0x0A,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProxyProtocolVersion {
+    V1,
+    V2,
+}
+
+impl ProxyProtocolVersion {
+    pub fn from(str: &str) -> Result<Self> {
+        match str {
+            "v1" => Ok(ProxyProtocolVersion::V1),
+            "v2" => Ok(ProxyProtocolVersion::V2),
+            _ => Err(anyhow::anyhow!("不支持的 PROXY 协议版本: {}", str)),
+        }
+    }
+
+    pub fn show(&self) -> &'static str {
+        match self {
+            ProxyProtocolVersion::V1 => "v1",
+            ProxyProtocolVersion::V2 => "v2",
+        }
+    }
+}
+
+// 读取 PROXY 协议头，只消费头部字节，返回 (源地址, 目标地址)
+// LOCAL 命令或 UNKNOWN 协议族时返回 None
+pub async fn read_proxy_header<R>(reader: &mut R) -> Result<Option<(SocketAddr, SocketAddr)>>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut header = [0u8; 12];
+    reader.read_exact(&mut header).await?;
+
+    if header == V2_SIGNATURE {
+        return read_v2(reader).await;
+    }
+    if header.starts_with(b"PROXY ") {
+        return read_v1(reader, &header).await;
+    }
+    Err(anyhow::anyhow!("缺失 PROXY 协议头"))
+}
+
+async fn read_v1<R>(reader: &mut R, prefix: &[u8]) -> Result<Option<(SocketAddr, SocketAddr)>>
+where
+    R: AsyncRead + Unpin,
+{
+    // v1 头部最长 107 字节
+    let mut line = prefix.to_vec();
+    while !line.ends_with(b"\r\n") {
+        if line.len() >= 107 {
+            return Err(anyhow::anyhow!("PROXY v1 协议头过长"));
+        }
+        line.push(reader.read_u8().await?);
+    }
+
+    let line = String::from_utf8(line)?;
+    let parts: Vec<&str> = line.trim_end().split(' ').collect();
+    match parts.as_slice() {
+        ["PROXY", "UNKNOWN", ..] => Ok(None),
+        [
+            "PROXY",
+            "TCP4" | "TCP6",
+            source,
+            destination,
+            source_port,
+            destination_port,
+        ] => {
+            let source = SocketAddr::new(source.parse()?, source_port.parse()?);
+            let destination = SocketAddr::new(destination.parse()?, destination_port.parse()?);
+            Ok(Some((source, destination)))
+        }
+        _ => Err(anyhow::anyhow!(
+            "无效的 PROXY v1 协议头: {}",
+            line.trim_end()
+        )),
+    }
+}
+
+async fn read_v2<R>(reader: &mut R) -> Result<Option<(SocketAddr, SocketAddr)>>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut header = [0u8; 4];
+    reader.read_exact(&mut header).await?;
+    let version_command = header[0];
+    let family = header[1];
+    let length = u16::from_be_bytes([header[2], header[3]]) as usize;
+
+    let mut body = vec![0u8; length];
+    reader.read_exact(&mut body).await?;
+
+    if version_command >> 4 != 0x2 {
+        return Err(anyhow::anyhow!("无效的 PROXY v2 版本"));
+    }
+    // LOCAL 命令，例如负载均衡器的健康检查
+    if version_command & 0x0F == 0x0 {
+        return Ok(None);
+    }
+
+    match family >> 4 {
+        0x1 if body.len() >= 12 => {
+            let source = Ipv4Addr::new(body[0], body[1], body[2], body[3]);
+            let destination = Ipv4Addr::new(body[4], body[5], body[6], body[7]);
+            let source_port = u16::from_be_bytes([body[8], body[9]]);
+            let destination_port = u16::from_be_bytes([body[10], body[11]]);
+            Ok(Some((
+                SocketAddr::new(IpAddr::V4(source), source_port),
+                SocketAddr::new(IpAddr::V4(destination), destination_port),
+            )))
+        }
+        0x2 if body.len() >= 36 => {
+            let source: [u8; 16] = body[0..16].try_into()?;
+            let destination: [u8; 16] = body[16..32].try_into()?;
+            let source_port = u16::from_be_bytes([body[32], body[33]]);
+            let destination_port = u16::from_be_bytes([body[34], body[35]]);
+            Ok(Some((
+                SocketAddr::new(IpAddr::V6(Ipv6Addr::from(source)), source_port),
+                SocketAddr::new(IpAddr::V6(Ipv6Addr::from(destination)), destination_port),
+            )))
+        }
+        0x1 | 0x2 => Err(anyhow::anyhow!("PROXY v2 地址长度不足: {}", body.len())),
+        _ => Ok(None),
+    }
+}
+
+// 生成发往上游的 PROXY 协议头
+pub fn encode_proxy_header(
+    version: ProxyProtocolVersion,
+    source: SocketAddr,
+    destination: SocketAddr,
+) -> Vec<u8> {
+    // 源地址和目标地址协议族不同时，统一映射为 IPv6
+    let (source_ip, destination_ip) = match (source.ip(), destination.ip()) {
+        (IpAddr::V4(source), IpAddr::V4(destination)) => {
+            (IpAddr::V4(source), IpAddr::V4(destination))
+        }
+        (source, destination) => (
+            IpAddr::V6(to_ipv6(source)),
+            IpAddr::V6(to_ipv6(destination)),
+        ),
+    };
+
+    match version {
+        ProxyProtocolVersion::V1 => {
+            let family = if source_ip.is_ipv4() { "TCP4" } else { "TCP6" };
+            format!(
+                "PROXY {} {} {} {} {}\r\n",
+                family,
+                source_ip,
+                destination_ip,
+                source.port(),
+                destination.port()
+            )
+            .into_bytes()
+        }
+        ProxyProtocolVersion::V2 => {
+            let mut header = V2_SIGNATURE.to_vec();
+            header.push(0x21); // 版本 2，PROXY 命令
+            match (source_ip, destination_ip) {
+                (IpAddr::V4(source_ip), IpAddr::V4(destination_ip)) => {
+                    header.push(0x11); // TCP over IPv4
+                    header.extend_from_slice(&12u16.to_be_bytes());
+                    header.extend_from_slice(&source_ip.octets());
+                    header.extend_from_slice(&destination_ip.octets());
+                }
+                _ => {
+                    header.push(0x21); // TCP over IPv6
+                    header.extend_from_slice(&36u16.to_be_bytes());
+                    header.extend_from_slice(&to_ipv6(source_ip).octets());
+                    header.extend_from_slice(&to_ipv6(destination_ip).octets());
+                }
+            }
+            header.extend_from_slice(&source.port().to_be_bytes());
+            header.extend_from_slice(&destination.port().to_be_bytes());
+            header
+        }
+    }
+}
+
+fn to_ipv6(ip: IpAddr) -> Ipv6Addr {
+    match ip {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+        IpAddr::V6(ip) => ip,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addresses(source: &str, destination: &str) -> (SocketAddr, SocketAddr) {
+        (source.parse().unwrap(), destination.parse().unwrap())
+    }
+
+    async fn read(mut bytes: &[u8]) -> Result<Option<(SocketAddr, SocketAddr)>> {
+        read_proxy_header(&mut bytes).await
+    }
+
+    #[tokio::test]
+    async fn round_trip() {
+        let cases = [
+            addresses("192.0.2.1:12345", "198.51.100.2:443"),
+            addresses("[2001:db8::1]:12345", "[2001:db8::2]:443"),
+        ];
+        for version in [ProxyProtocolVersion::V1, ProxyProtocolVersion::V2] {
+            for (source, destination) in cases {
+                let header = encode_proxy_header(version, source, destination);
+                let parsed = read(&header).await.unwrap();
+                assert_eq!(parsed, Some((source, destination)), "{:?}", version);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn mixed_families_map_to_ipv6() {
+        let (source, destination) = addresses("192.0.2.1:1000", "[2001:db8::2]:443");
+        for version in [ProxyProtocolVersion::V1, ProxyProtocolVersion::V2] {
+            let header = encode_proxy_header(version, source, destination);
+            let (parsed_source, parsed_destination) = read(&header).await.unwrap().unwrap();
+            assert_eq!(
+                parsed_source.ip(),
+                IpAddr::V6(Ipv4Addr::new(192, 0, 2, 1).to_ipv6_mapped())
+            );
+            assert_eq!(parsed_source.port(), 1000);
+            assert_eq!(parsed_destination, destination);
+        }
+    }
+
+    #[tokio::test]
+    async fn consumes_only_header() {
+        let (source, destination) = addresses("192.0.2.1:1000", "198.51.100.2:80");
+        for version in [ProxyProtocolVersion::V1, ProxyProtocolVersion::V2] {
+            let mut bytes = encode_proxy_header(version, source, destination);
+            bytes.extend_from_slice(b"GET / HTTP/1.1\r\n");
+            let mut reader = bytes.as_slice();
+            read_proxy_header(&mut reader).await.unwrap();
+            assert_eq!(reader, b"GET / HTTP/1.1\r\n");
+        }
+    }
+
+    #[tokio::test]
+    async fn v1_unknown() {
+        assert_eq!(read(b"PROXY UNKNOWN\r\n").await.unwrap(), None);
+        assert_eq!(
+            read(b"PROXY UNKNOWN ffff::1 ffff::2 1 2\r\n")
+                .await
+                .unwrap(),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn v2_local() {
+        let mut header = V2_SIGNATURE.to_vec();
+        header.extend_from_slice(&[0x20, 0x00, 0x00, 0x00]);
+        assert_eq!(read(&header).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn truncated() {
+        let (source, destination) = addresses("192.0.2.1:1000", "198.51.100.2:80");
+        for version in [ProxyProtocolVersion::V1, ProxyProtocolVersion::V2] {
+            let header = encode_proxy_header(version, source, destination);
+            for len in [0, 5, 12, 15, header.len() - 1] {
+                assert!(read(&header[..len]).await.is_err(), "{:?} {}", version, len);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn invalid() {
+        assert!(read(b"GET / HTTP/1.1\r\n\r\n").await.is_err());
+        assert!(read(b"PROXY TCP4 1.2.3.4 5.6.7.8 80\r\n").await.is_err());
+        assert!(
+            read(b"PROXY TCP4 1.2.3.4 5.6.7.8 99999 80\r\n")
+                .await
+                .is_err()
+        );
+        let mut long = b"PROXY TCP4 ".to_vec();
+        long.resize(200, b'1');
+        assert!(read(&long).await.is_err());
+
+        // 声明的长度不足以容纳地址
+        let mut header = V2_SIGNATURE.to_vec();
+        header.extend_from_slice(&[0x21, 0x11, 0x00, 0x04, 1, 2, 3, 4]);
+        assert!(read(&header).await.is_err());
+
+        // 版本号不是 2
+        let mut header = V2_SIGNATURE.to_vec();
+        header.extend_from_slice(&[0x11, 0x11, 0x00, 0x00]);
+        assert!(read(&header).await.is_err());
+    }
+}
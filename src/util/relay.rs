@@ -0,0 +1,112 @@
+use std::{fmt, io};
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+const BUFFER_SIZE: usize = 16 * 1024;
+
+// 隧道结束的原因
+#[derive(Debug)]
+pub enum Cause {
+    // 两个方向都正常结束
+    Closed,
+    // 客户端读写出错
+    Client(io::Error),
+    // 上游读写出错
+    Upstream(io::Error),
+}
+
+impl fmt::Display for Cause {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Cause::Closed => write!(f, "正常关闭"),
+            Cause::Client(e) => write!(f, "客户端错误: {}", e),
+            Cause::Upstream(e) => write!(f, "上游错误: {}", e),
+        }
+    }
+}
+
+// 隧道的转发结果
+#[derive(Debug)]
+pub struct Relayed {
+    // 客户端到上游的字节数
+    pub upload: u64,
+    // 上游到客户端的字节数
+    pub download: u64,
+    pub cause: Cause,
+}
+
+impl fmt::Display for Relayed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "上传 {} 字节, 下载 {} 字节, {}",
+            self.upload, self.download, self.cause
+        )
+    }
+}
+
+// 单个方向的读写错误
+enum Half {
+    Read(io::Error),
+    Write(io::Error),
+}
+
+// 在客户端和上游之间双向转发数据
+// 每个方向独立传递 FIN: 一端读到 EOF 后关闭另一端的写方向，另一个方向继续转发，直到两个方向都结束
+// 任意方向出错时立即结束整个隧道
+pub async fn relay<R, W, U>(client_reader: &mut R, client_writer: &mut W, upstream: U) -> Relayed
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+    U: AsyncRead + AsyncWrite + Unpin,
+{
+    let (mut upstream_reader, mut upstream_writer) = tokio::io::split(upstream);
+    let mut upload = 0;
+    let mut download = 0;
+
+    let result = tokio::try_join!(
+        async {
+            copy_half(client_reader, &mut upstream_writer, &mut upload)
+                .await
+                .map_err(|half| match half {
+                    Half::Read(e) => Cause::Client(e),
+                    Half::Write(e) => Cause::Upstream(e),
+                })
+        },
+        async {
+            copy_half(&mut upstream_reader, client_writer, &mut download)
+                .await
+                .map_err(|half| match half {
+                    Half::Read(e) => Cause::Upstream(e),
+                    Half::Write(e) => Cause::Client(e),
+                })
+        },
+    );
+
+    Relayed {
+        upload,
+        download,
+        cause: result.err().unwrap_or(Cause::Closed),
+    }
+}
+
+// 转发到读端 EOF，然后关闭写端的写方向
+async fn copy_half<R, W>(reader: &mut R, writer: &mut W, count: &mut u64) -> Result<(), Half>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut buffer = vec![0u8; BUFFER_SIZE];
+    loop {
+        let read = reader.read(&mut buffer).await.map_err(Half::Read)?;
+        if read == 0 {
+            break;
+        }
+        writer
+            .write_all(&buffer[..read])
+            .await
+            .map_err(Half::Write)?;
+        *count += read as u64;
+    }
+    writer.shutdown().await.map_err(Half::Write)
+}
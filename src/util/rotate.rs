@@ -0,0 +1,122 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use chrono::{DateTime, Utc};
+
+use crate::common::config::Rotation;
+
+// 按大小或时间轮转的日志文件，轮转时将当前文件重命名为 <文件名>.<时间>
+pub struct RotatingFile {
+    path: PathBuf,
+    max_size: Option<u64>,
+    rotation: Rotation,
+    keep: usize,
+    file: File,
+    size: u64,
+    // 当前文件所属的时间段，进入新的时间段时轮转
+    period: String,
+}
+
+impl RotatingFile {
+    pub fn open(
+        path: &str,
+        max_size: Option<u64>,
+        rotation: Rotation,
+        keep: usize,
+    ) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let metadata = file.metadata()?;
+        // 已有文件按最后修改时间计算时间段，重启后也能按时轮转
+        let modified: DateTime<Utc> = metadata.modified().unwrap_or(SystemTime::now()).into();
+        Ok(RotatingFile {
+            path: PathBuf::from(path),
+            max_size,
+            rotation,
+            keep,
+            file,
+            size: metadata.len(),
+            period: period(rotation, modified),
+        })
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        let suffix = Utc::now().format("%Y%m%d-%H%M%S").to_string();
+        let mut rotated = PathBuf::from(format!("{}.{}", self.path.display(), suffix));
+        // 同一秒内多次轮转时追加序号
+        let mut index = 1;
+        while rotated.exists() {
+            rotated = PathBuf::from(format!("{}.{}.{}", self.path.display(), suffix, index));
+            index += 1;
+        }
+        fs::rename(&self.path, &rotated)?;
+
+        self.file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.size = 0;
+        self.period = period(self.rotation, Utc::now());
+        self.cleanup()
+    }
+
+    // 只保留最近的 keep 个历史文件
+    fn cleanup(&self) -> io::Result<()> {
+        if self.keep == 0 {
+            return Ok(());
+        }
+        let directory = match self.path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        let Some(name) = self.path.file_name().and_then(|name| name.to_str()) else {
+            return Ok(());
+        };
+        let prefix = format!("{}.", name);
+
+        let mut rotated: Vec<PathBuf> = fs::read_dir(directory)?
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_name().to_string_lossy().starts_with(&prefix))
+            .map(|entry| entry.path())
+            .collect();
+        // 文件名中的时间可以直接按字符串排序
+        rotated.sort();
+        let excess = rotated.len().saturating_sub(self.keep);
+        for path in &rotated[..excess] {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+}
+
+// 每次写入前检查是否需要轮转，写入的内容不会被拆分到两个文件
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let length = buf.len() as u64;
+        let oversize = self
+            .max_size
+            .is_some_and(|max| self.size > 0 && self.size + length > max);
+        if oversize || self.period != period(self.rotation, Utc::now()) {
+            self.rotate()?;
+        }
+        self.file.write_all(buf)?;
+        self.size += length;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+// 时间所属的轮转时间段，UTC 时间
+fn period(rotation: Rotation, time: DateTime<Utc>) -> String {
+    match rotation {
+        Rotation::Never => String::new(),
+        Rotation::Hourly => time.format("%Y%m%d%H").to_string(),
+        Rotation::Daily => time.format("%Y%m%d").to_string(),
+    }
+}